@@ -0,0 +1,27 @@
+// Renders one frame of the cube without a window and writes it to a PNG, the
+// way a tool embedding the renderer would.
+//
+//   cargo run --example headless -- cube.png
+use wgpu_testing::capture::FrameReadback;
+use wgpu_testing::{ModelPath, Renderer, RendererOptions};
+
+fn main() -> anyhow::Result<()> {
+    let out = std::env::args().nth(1).unwrap_or_else(|| "headless.png".to_string());
+    let cube = ModelPath { file_name: "cube.obj".to_string(), subfolder: "cube".to_string() };
+    let mut renderer = pollster::block_on(Renderer::headless(640, 480, &cube, &RendererOptions::default()));
+    renderer.frame_scene();
+    renderer.update(renderer.camera_clock.step());
+    renderer.render()?;
+
+    // the target keeps its texture after the frame, so it can be read back
+    let texture = renderer.target.texture().expect("texture targets have a texture");
+    let (width, height) = renderer.target.size();
+    let readback = FrameReadback::new(&renderer.device, width, height, renderer.target.format())?;
+    let mut encoder = renderer.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    readback.copy(&mut encoder, texture);
+    renderer.queue.submit(std::iter::once(encoder.finish()));
+    let pixels = readback.read(&renderer.device)?;
+    image::save_buffer(&out, &pixels, width, height, image::ColorType::Rgba8)?;
+    println!("Wrote {}", out);
+    Ok(())
+}
@@ -0,0 +1,66 @@
+// Builds a scene through the library instead of the viewer binary: a ring of
+// tinted cubes, with the renderer's own input handling and hotkeys left in
+// place.
+//
+//   cargo run --example scene
+use cgmath::{Deg, Quaternion, Rotation3, Vector3};
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop},
+    window::WindowBuilder,
+};
+use wgpu_testing::{Instance, ModelPath, Renderer, Scene, RendererOptions};
+
+const CUBES: u32 = 12;
+
+fn ring(radius: f32) -> Vec<Instance> {
+    (0..CUBES).map(|i| {
+        let angle = Deg(360.0 * i as f32 / CUBES as f32);
+        let mut instance = Instance::new(Quaternion::from_angle_y(angle) * Vector3::new(radius, 0.0, 0.0));
+        instance.rotation = Quaternion::from_angle_y(angle);
+        instance.scale = Vector3::new(0.5, 0.5, 0.5);
+        instance.tint = [i as f32 / CUBES as f32, 0.6, 1.0 - i as f32 / CUBES as f32, 1.0];
+        instance
+    }).collect()
+}
+
+fn main() -> anyhow::Result<()> {
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new().with_title("scene example").build(&event_loop)?;
+
+    let cube = ModelPath { file_name: "cube.obj".to_string(), subfolder: "cube".to_string() };
+    let mut renderer = pollster::block_on(Renderer::new(window, &cube, &RendererOptions::default()));
+    let model = renderer.load_model(&cube)?;
+    renderer.set_scene(Scene::new(model, ring(4.0)));
+    renderer.frame_scene();
+
+    let mut last_render_time = instant::Instant::now();
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        match event {
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
+                renderer.process_mouse_motion(delta.0, delta.1);
+            }
+            Event::WindowEvent { ref event, .. } if !renderer.input(event) => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(size) => renderer.resize(size.width, size.height),
+                _ => {}
+            },
+            Event::RedrawRequested(_) => {
+                let now = instant::Instant::now();
+                renderer.update(now - last_render_time);
+                last_render_time = now;
+                match renderer.render() {
+                    Ok(_) => {}
+                    Err(wgpu::SurfaceError::Lost) => {
+                        let (width, height) = renderer.target.size();
+                        renderer.resize(width, height);
+                    }
+                    Err(e) => eprintln!("{:?}", e),
+                }
+            }
+            Event::MainEventsCleared => renderer.window().unwrap().request_redraw(),
+            _ => {}
+        }
+    });
+}
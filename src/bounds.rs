@@ -0,0 +1,122 @@
+use cgmath::*;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Self {
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for p in points {
+            min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Self { min, max }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self::from_points([self.min, self.max, other.min, other.max])
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+        ]
+    }
+
+    // Box enclosing this one after it has been transformed, so rotations make it grow
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        Self::from_points(self.corners().iter().map(|c| matrix.transform_point(*c)))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // Centered on the points' AABB, which is cheap and close enough for culling
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>> + Clone>(points: I) -> Self {
+        let center = Aabb::from_points(points.clone()).center();
+        let radius = points.into_iter()
+            .map(|p| p.distance(center))
+            .fold(0.0, f32::max);
+        Self { center, radius }
+    }
+
+    // Non-uniform scale grows the radius by the largest axis so the sphere stays conservative
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        let scale = matrix.x.truncate().magnitude()
+            .max(matrix.y.truncate().magnitude())
+            .max(matrix.z.truncate().magnitude());
+        Self {
+            center: matrix.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aabb_encloses_points() {
+        let aabb = Aabb::from_points([
+            Point3::new(1.0, -2.0, 0.5),
+            Point3::new(-1.0, 3.0, 0.0),
+            Point3::new(0.0, 0.0, -4.0),
+        ]);
+        assert_eq!(aabb.min, Point3::new(-1.0, -2.0, -4.0));
+        assert_eq!(aabb.max, Point3::new(1.0, 3.0, 0.5));
+    }
+
+    #[test]
+    fn aabb_transform_grows_under_rotation() {
+        let aabb = Aabb::from_points([Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0)]);
+        let rotated = aabb.transform(&Matrix4::from_angle_y(Deg(45.0)));
+        assert!((rotated.max.x - 2.0f32.sqrt()).abs() < 1e-5);
+        assert!((rotated.max.y - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sphere_encloses_points() {
+        let points = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(0.0, 2.0, 0.0),
+        ];
+        let sphere = BoundingSphere::from_points(points);
+        assert_eq!(sphere.center, Point3::new(1.0, 1.0, 0.0));
+        for p in points {
+            assert!(p.distance(sphere.center) <= sphere.radius + 1e-5);
+        }
+    }
+
+    #[test]
+    fn sphere_transform_uses_largest_scale() {
+        let sphere = BoundingSphere { center: Point3::new(1.0, 0.0, 0.0), radius: 1.0 };
+        let matrix = Matrix4::from_translation(Vector3::new(0.0, 5.0, 0.0))
+            * Matrix4::from_nonuniform_scale(1.0, 3.0, 2.0);
+        let transformed = sphere.transform(&matrix);
+        assert_eq!(transformed.center, Point3::new(1.0, 5.0, 0.0));
+        assert!((transformed.radius - 3.0).abs() < 1e-5);
+    }
+}
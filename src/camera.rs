@@ -1,10 +1,13 @@
 use cgmath::*;
-use winit::event::{VirtualKeyCode, ElementState, MouseScrollDelta};
+use winit::event::MouseScrollDelta;
 use winit::dpi::PhysicalPosition;
 use std::f32::consts::FRAC_PI_2;
 use instant::Duration;
 
-#[derive(Debug)]
+use crate::bounds::Aabb;
+use crate::input_map::Action;
+
+#[derive(Clone, Debug)]
 pub struct Camera {
     pub position: Point3<f32>,
     yaw: Rad<f32>,
@@ -19,6 +22,15 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+// depth' = 1 - depth for w = 1
+#[rustfmt::skip]
+const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0, 1.0, 1.0,
+);
+
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 impl Camera {
@@ -38,27 +50,122 @@ impl Camera {
         }
     }
 
-    pub fn calc_matrix(&self) -> Matrix4<f32> {
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    pub fn set_orientation(&mut self, yaw: Rad<f32>, pitch: Rad<f32>) {
+        self.yaw = yaw;
+        self.pitch = pitch;
+    }
+
+    // For rendering between two simulation steps
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        Camera {
+            position: self.position + (other.position - self.position) * t,
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+        }
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(
+            cos_pitch * cos_yaw,
+            sin_pitch,
+            cos_pitch * sin_yaw
+        ).normalize()
+    }
 
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
         Matrix4::look_to_rh(
             self.position,
-            Vector3::new(
-                cos_pitch * cos_yaw,
-                sin_pitch,
-                cos_pitch * sin_yaw
-            ).normalize(),
+            self.forward(),
             Vector3::unit_y(),
         )
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProjectionMode {
+    Perspective,
+    Orthographic,
+}
+
+// Standard maps near..far to 0..1. ReverseZ maps near to 1 and infinity to 0,
+// which spreads float precision evenly over distance and removes the far plane
+// for perspective. Pipelines are built for both, see DepthMode::ALL.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    Standard,
+    ReverseZ,
+}
+
+impl DepthMode {
+    pub const ALL: [DepthMode; 2] = [DepthMode::Standard, DepthMode::ReverseZ];
+
+    pub fn next(self) -> Self {
+        match self {
+            Self::Standard => Self::ReverseZ,
+            Self::ReverseZ => Self::Standard,
+        }
+    }
+
+    pub fn compare(self) -> wgpu::CompareFunction {
+        match self {
+            Self::Standard => wgpu::CompareFunction::Less,
+            Self::ReverseZ => wgpu::CompareFunction::GreaterEqual,
+        }
+    }
+
+    // For passes drawn on top of the depth written by the shaded pass
+    pub fn compare_equal(self) -> wgpu::CompareFunction {
+        match self {
+            Self::Standard => wgpu::CompareFunction::LessEqual,
+            Self::ReverseZ => wgpu::CompareFunction::GreaterEqual,
+        }
+    }
+
+    // Depth bias moving fragments towards the camera
+    pub fn bias_towards_camera(self, constant: i32, slope_scale: f32) -> wgpu::DepthBiasState {
+        let sign = match self {
+            Self::Standard => -1,
+            Self::ReverseZ => 1,
+        };
+        wgpu::DepthBiasState {
+            constant: constant * sign,
+            slope_scale: slope_scale * sign as f32,
+            clamp: 0.0,
+        }
+    }
+
+    pub fn clear_value(self) -> f32 {
+        match self {
+            Self::Standard => 1.0,
+            Self::ReverseZ => 0.0,
+        }
+    }
+}
+
+// How long switching between perspective and orthographic takes
+const PROJECTION_TRANSITION_SECS: f32 = 0.3;
+
 pub struct Projection {
     aspect: f32,
     fovy: Rad<f32>,
     znear: f32,
     zfar: f32,
+    mode: ProjectionMode,
+    depth_mode: DepthMode,
+    // half the visible height in orthographic mode
+    ortho_height: f32,
+    // 0 is fully perspective, 1 fully orthographic, animated towards mode
+    ortho_blend: f32,
 }
 
 impl Projection {
@@ -74,6 +181,10 @@ impl Projection {
             fovy: fovy.into(),
             znear,
             zfar,
+            mode: ProjectionMode::Perspective,
+            depth_mode: DepthMode::Standard,
+            ortho_height: 5.0,
+            ortho_blend: 0.0,
         }
     }
 
@@ -81,8 +192,108 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    pub fn set_fovy<F: Into<Rad<f32>>>(&mut self, fovy: F) {
+        self.fovy = fovy.into();
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
+    pub fn set_zfar(&mut self, zfar: f32) {
+        self.zfar = zfar;
+    }
+
+    pub fn depth_mode(&self) -> DepthMode {
+        self.depth_mode
+    }
+
+    pub fn set_depth_mode(&mut self, depth_mode: DepthMode) {
+        self.depth_mode = depth_mode;
+    }
+
+    pub fn mode(&self) -> ProjectionMode {
+        self.mode
+    }
+
+    // Starts the animated transition, focus_distance is the depth that keeps
+    // its on screen size while switching
+    pub fn set_mode(&mut self, mode: ProjectionMode, focus_distance: f32) {
+        self.set_focus_distance(focus_distance);
+        self.mode = mode;
+    }
+
+    pub fn set_focus_distance(&mut self, focus_distance: f32) {
+        self.ortho_height = focus_distance * (self.fovy / 2.0).tan();
+    }
+
+    // Zoom by extent, only has an effect in orthographic mode
+    pub fn zoom(&mut self, delta: &MouseScrollDelta) {
+        self.ortho_height = (self.ortho_height * 0.9f32.powf(scroll_lines(delta))).max(0.01);
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        let step = dt.as_secs_f32() / PROJECTION_TRANSITION_SECS;
+        self.ortho_blend = match self.mode {
+            ProjectionMode::Perspective => (self.ortho_blend - step).max(0.0),
+            ProjectionMode::Orthographic => (self.ortho_blend + step).min(1.0),
+        };
+    }
+
+    // Half the visible height at distance from the camera, following the
+    // transition between the projections
+    pub fn half_height_at(&self, distance: f32) -> f32 {
+        let perspective = distance * (self.fovy / 2.0).tan();
+        let t = self.ortho_blend * self.ortho_blend * (3.0 - 2.0 * self.ortho_blend);
+        perspective * (1.0 - t) + self.ortho_height * t
+    }
+
+    // Already in wgpu's 0..1 clip space depth
+    fn perspective_matrix(&self) -> Matrix4<f32> {
+        match self.depth_mode {
+            DepthMode::Standard => OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar),
+            DepthMode::ReverseZ => {
+                // infinite far plane, depth is znear / view distance
+                let f = 1.0 / (self.fovy / 2.0).tan();
+                Matrix4::new(
+                    f / self.aspect, 0.0, 0.0, 0.0,
+                    0.0, f, 0.0, 0.0,
+                    0.0, 0.0, 0.0, -1.0,
+                    0.0, 0.0, self.znear, 0.0,
+                )
+            }
+        }
+    }
+
+    // Orthographic depth is linear so there's no infinite version, reverse-z
+    // just flips the range
+    fn orthographic_matrix(&self) -> Matrix4<f32> {
+        let width = self.ortho_height * self.aspect;
+        let matrix = OPENGL_TO_WGPU_MATRIX
+            * ortho(-width, width, -self.ortho_height, self.ortho_height, self.znear, self.zfar);
+        match self.depth_mode {
+            DepthMode::Standard => matrix,
+            DepthMode::ReverseZ => REVERSE_Z_MATRIX * matrix,
+        }
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        let perspective = self.perspective_matrix();
+        let orthographic = self.orthographic_matrix();
+        // Blending the matrices keeps anything at the focus distance the same
+        // size for the whole transition, smoothstep eases in and out
+        let t = self.ortho_blend * self.ortho_blend * (3.0 - 2.0 * self.ortho_blend);
+        if t <= 0.0 {
+            perspective
+        } else if t >= 1.0 {
+            orthographic
+        } else {
+            perspective * (1.0 - t) + orthographic * t
+        }
     }
 }
 
@@ -93,6 +304,12 @@ pub struct CameraUniform{
     view_proj: [[f32; 4]; 4],
 }
 
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
@@ -139,30 +356,30 @@ impl CameraController {
         }
     }
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool{
-        let amount = if state == ElementState::Pressed { 1.0 } else { 0.0 };
-        match key {
-            VirtualKeyCode::W => {
+    pub fn process_action(&mut self, action: Action, pressed: bool) -> bool {
+        let amount = if pressed { 1.0 } else { 0.0 };
+        match action {
+            Action::CameraForward => {
                 self.amount_forward = amount;
                 true
             }
-            VirtualKeyCode::S => {
+            Action::CameraBackward => {
                 self.amount_backward = amount;
                 true
             }
-            VirtualKeyCode::A  => {
+            Action::CameraLeft => {
                 self.amount_left = amount;
                 true
             }
-            VirtualKeyCode::D  => {
+            Action::CameraRight => {
                 self.amount_right = amount;
                 true
             }
-            VirtualKeyCode::Space => {
+            Action::CameraUp => {
                 self.amount_up = amount;
                 true
             }
-            VirtualKeyCode::LControl => {
+            Action::CameraDown => {
                 self.amount_down = amount;
                 true
             }
@@ -227,3 +444,165 @@ impl CameraController {
         }
     }
 }
+
+// Wheel notches, using the same 100 pixels per line as CameraController
+fn scroll_lines(delta: &MouseScrollDelta) -> f32 {
+    match delta {
+        MouseScrollDelta::LineDelta(_, scroll) => *scroll,
+        MouseScrollDelta::PixelDelta(PhysicalPosition {
+            y: scroll,
+            ..
+        }) => *scroll as f32 / 100.0,
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ViewPreset {
+    Front,
+    Back,
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+impl ViewPreset {
+    pub fn from_action(action: Action) -> Option<Self> {
+        match action {
+            Action::ViewFront => Some(Self::Front),
+            Action::ViewBack => Some(Self::Back),
+            Action::ViewRight => Some(Self::Right),
+            Action::ViewLeft => Some(Self::Left),
+            Action::ViewTop => Some(Self::Top),
+            Action::ViewBottom => Some(Self::Bottom),
+            _ => None,
+        }
+    }
+
+    // Camera yaw and pitch looking at the scene from this side
+    pub fn yaw_pitch(self) -> (Rad<f32>, Rad<f32>) {
+        match self {
+            Self::Front => (Deg(-90.0).into(), Rad(0.0)),
+            Self::Back => (Deg(90.0).into(), Rad(0.0)),
+            Self::Right => (Deg(180.0).into(), Rad(0.0)),
+            Self::Left => (Rad(0.0), Rad(0.0)),
+            Self::Top => (Deg(-90.0).into(), Rad(-SAFE_FRAC_PI_2)),
+            Self::Bottom => (Deg(-90.0).into(), Rad(SAFE_FRAC_PI_2)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    Fly,
+    Orbit,
+}
+
+// Turntable camera around a target point. Yaw and pitch are kept on the Camera
+// itself, so switching to and from the fly controller keeps the current view.
+#[derive(Debug)]
+pub struct OrbitController {
+    pub target: Point3<f32>,
+    pub distance: f32,
+    rotating: bool,
+    panning: bool,
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+    pan_horizontal: f32,
+    pan_vertical: f32,
+    scroll: f32,
+    sensitivity: f32,
+}
+
+impl OrbitController {
+    pub fn new(sensitivity: f32) -> Self {
+        Self {
+            target: Point3::origin(),
+            distance: 10.0,
+            rotating: false,
+            panning: false,
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+            pan_horizontal: 0.0,
+            pan_vertical: 0.0,
+            scroll: 0.0,
+            sensitivity,
+        }
+    }
+
+    // Orbit around whatever the camera is currently looking at, `focus` only
+    // picks how far along the view direction the target sits
+    pub fn attach(&mut self, camera: &Camera, focus: Point3<f32>) {
+        let forward = camera.forward();
+        self.distance = (focus - camera.position).dot(forward).max(0.1);
+        self.target = camera.position + forward * self.distance;
+    }
+
+    // Looks at the box from the current direction, far enough back that its
+    // bounding sphere fits the vertical field of view
+    pub fn frame(&mut self, camera: &mut Camera, aabb: &Aabb, fovy: Rad<f32>) {
+        let radius = (aabb.max - aabb.min).magnitude() / 2.0;
+        self.target = aabb.center();
+        self.distance = radius / (fovy / 2.0).sin();
+        camera.position = self.target - camera.forward() * self.distance;
+    }
+
+    pub fn set_view(&mut self, camera: &mut Camera, preset: ViewPreset) {
+        (camera.yaw, camera.pitch) = preset.yaw_pitch();
+        camera.position = self.target - camera.forward() * self.distance;
+    }
+
+    pub fn process_action(&mut self, action: Action, pressed: bool) -> bool {
+        match action {
+            Action::CameraLook => {
+                self.rotating = pressed;
+                true
+            }
+            Action::CameraPan => {
+                self.panning = pressed;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        if self.panning {
+            self.pan_horizontal += mouse_dx as f32;
+            self.pan_vertical += mouse_dy as f32;
+        } else if self.rotating {
+            self.rotate_horizontal += mouse_dx as f32;
+            self.rotate_vertical += mouse_dy as f32;
+        }
+    }
+
+    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll += scroll_lines(delta);
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+
+        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
+        camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
+        camera.pitch = Rad(camera.pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2));
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        // Pan in the view plane, scaled by distance so the target roughly
+        // follows the cursor
+        let forward = camera.forward();
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let up = right.cross(forward);
+        let pan_scale = self.distance * 0.001;
+        self.target += (-right * self.pan_horizontal + up * self.pan_vertical) * pan_scale;
+        self.pan_horizontal = 0.0;
+        self.pan_vertical = 0.0;
+
+        // Dolly, each wheel notch moves 10% closer
+        self.distance = (self.distance * 0.9f32.powf(self.scroll)).max(0.1);
+        self.scroll = 0.0;
+
+        camera.position = self.target - forward * self.distance;
+    }
+}
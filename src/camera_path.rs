@@ -0,0 +1,392 @@
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{anyhow, bail, Context};
+use cgmath::*;
+
+use crate::camera::{Camera, Projection};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraKeyframe {
+    // seconds from the start of the path
+    pub time: f32,
+    pub position: Point3<f32>,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+    pub fovy: Rad<f32>,
+}
+
+impl CameraKeyframe {
+    pub fn from_camera(time: f32, camera: &Camera, projection: &Projection) -> Self {
+        Self {
+            time,
+            position: camera.position,
+            yaw: camera.yaw(),
+            pitch: camera.pitch(),
+            fovy: projection.fovy(),
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera, projection: &mut Projection) {
+        camera.position = self.position;
+        camera.set_orientation(self.yaw, self.pitch);
+        projection.set_fovy(self.fovy);
+    }
+
+    fn channels(&self) -> [f32; 6] {
+        [self.position.x, self.position.y, self.position.z, self.yaw.0, self.pitch.0, self.fovy.0]
+    }
+
+    fn from_channels(time: f32, c: [f32; 6]) -> Self {
+        Self {
+            time,
+            position: Point3::new(c[0], c[1], c[2]),
+            yaw: Rad(c[3]),
+            pitch: Rad(c[4]),
+            fovy: Rad(c[5]),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    // Passes through every keyframe with continuous velocity, can overshoot
+    CatmullRom,
+    // Cubic Bezier with automatic handles that are flattened on keyframes
+    // where a channel turns around, so it never overshoots a keyframe
+    Bezier,
+}
+
+impl Interpolation {
+    pub fn next(self) -> Self {
+        match self {
+            Self::CatmullRom => Self::Bezier,
+            Self::Bezier => Self::CatmullRom,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::CatmullRom => "catmull-rom",
+            Self::Bezier => "bezier",
+        }
+    }
+
+    // Interpolates between p1 and p2, p0 and p3 are the neighbouring keyframes
+    fn interpolate(self, p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+        match self {
+            Self::CatmullRom => {
+                let t2 = t * t;
+                let t3 = t2 * t;
+                0.5 * (2.0 * p1
+                    + (p2 - p0) * t
+                    + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+                    + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+            }
+            Self::Bezier => {
+                let tangent = |prev: f32, current: f32, next: f32| {
+                    if (current - prev) * (next - current) <= 0.0 {
+                        0.0
+                    } else {
+                        (next - prev) / 2.0
+                    }
+                };
+                let c1 = p1 + tangent(p0, p1, p2) / 3.0;
+                let c2 = p2 - tangent(p1, p2, p3) / 3.0;
+                let u = 1.0 - t;
+                u * u * u * p1 + 3.0 * u * u * t * c1 + 3.0 * u * t * t * c2 + t * t * t * p2
+            }
+        }
+    }
+}
+
+// Keyframed fly-through. sample() only depends on the time passed in, so
+// playing back from DataUniform::time gives the same frames every run.
+#[derive(Clone, Debug)]
+pub struct CameraPath {
+    keyframes: Vec<CameraKeyframe>,
+    pub interpolation: Interpolation,
+    // When looping the camera flies from the last keyframe back to the first,
+    // arriving at duration
+    pub looping: bool,
+    pub duration: f32,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            interpolation: Interpolation::CatmullRom,
+            looping: false,
+            duration: 0.0,
+        }
+    }
+
+    // One turn around target at a fixed pitch. Not looping, so the yaw keeps
+    // increasing instead of spinning back from 360 degrees to 0.
+    pub fn turntable(target: Point3<f32>, distance: f32, pitch: Rad<f32>, fovy: Rad<f32>, duration: f32) -> Self {
+        // close enough that the spline stays within a fraction of a percent of the circle
+        const KEYFRAMES: u32 = 36;
+        let mut path = Self::new();
+        for i in 0..=KEYFRAMES {
+            let t = i as f32 / KEYFRAMES as f32;
+            let yaw = Rad::full_turn() * t;
+            let camera = Camera::new(target, yaw, pitch);
+            path.add(CameraKeyframe {
+                time: duration * t,
+                position: target - camera.forward() * distance,
+                yaw,
+                pitch,
+                fovy,
+            });
+        }
+        path.duration = duration;
+        path
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.duration = 0.0;
+    }
+
+    // Keeps the keyframes sorted by time, and leaves one average segment at
+    // the end of the duration for the loop back to the start
+    pub fn add(&mut self, keyframe: CameraKeyframe) {
+        let index = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+        let first = self.keyframes[0].time;
+        let last = self.keyframes[self.keyframes.len() - 1].time;
+        let segments = self.keyframes.len() - 1;
+        let loop_segment = if segments == 0 { 1.0 } else { (last - first) / segments as f32 };
+        self.duration = self.duration.max(last + loop_segment);
+    }
+
+    pub fn sample(&self, time: f32) -> Option<CameraKeyframe> {
+        let n = self.keyframes.len();
+        let first = self.keyframes.first()?;
+        let last = self.keyframes[n - 1];
+        if n == 1 {
+            return Some(CameraKeyframe { time, ..*first });
+        }
+
+        // index of the segment start, its end time and where in it we are
+        let (start, end_time, time) = if self.looping {
+            let period = self.duration - first.time;
+            let mut time = first.time + (time - first.time).rem_euclid(period);
+            if time >= last.time {
+                (n - 1, self.duration, time)
+            } else {
+                if time < first.time {
+                    time += period;
+                }
+                let start = self.keyframes.partition_point(|k| k.time <= time) - 1;
+                (start, self.keyframes[start + 1].time, time)
+            }
+        } else {
+            if time <= first.time {
+                return Some(CameraKeyframe { time, ..*first });
+            }
+            if time >= last.time {
+                return Some(CameraKeyframe { time, ..last });
+            }
+            let start = self.keyframes.partition_point(|k| k.time <= time) - 1;
+            (start, self.keyframes[start + 1].time, time)
+        };
+
+        let key = |i: isize| -> [f32; 6] {
+            let i = if self.looping {
+                i.rem_euclid(n as isize)
+            } else {
+                i.clamp(0, n as isize - 1)
+            };
+            self.keyframes[i as usize].channels()
+        };
+        let start_time = self.keyframes[start].time;
+        let t = ((time - start_time) / (end_time - start_time).max(f32::EPSILON)).clamp(0.0, 1.0);
+        let i = start as isize;
+        let (p0, p1, p2, p3) = (key(i - 1), key(i), key(i + 1), key(i + 2));
+        let channels = std::array::from_fn(|c| self.interpolation.interpolate(p0[c], p1[c], p2[c], p3[c], t));
+        Some(CameraKeyframe::from_channels(time, channels))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading camera path {}", path.display()))?;
+        text.parse()
+    }
+}
+
+// Plain text, one setting or keyframe per line:
+//   interpolation catmull-rom
+//   looping true
+//   duration 8
+//   key <time> <x> <y> <z> <yaw> <pitch> <fovy>    (angles in radians)
+impl std::fmt::Display for CameraPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "interpolation {}", self.interpolation.name())?;
+        writeln!(f, "looping {}", self.looping)?;
+        writeln!(f, "duration {}", self.duration)?;
+        for k in &self.keyframes {
+            writeln!(
+                f,
+                "key {} {} {} {} {} {} {}",
+                k.time, k.position.x, k.position.y, k.position.z, k.yaw.0, k.pitch.0, k.fovy.0,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CameraPath {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let mut path = Self::new();
+        let mut duration = None;
+        for (number, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let Some(name) = words.next() else {
+                continue;
+            };
+            let values = words.collect::<Vec<_>>();
+            let context = || format!("line {}: {}", number + 1, line);
+            match (name, values.as_slice()) {
+                ("interpolation", ["catmull-rom"]) => path.interpolation = Interpolation::CatmullRom,
+                ("interpolation", ["bezier"]) => path.interpolation = Interpolation::Bezier,
+                ("looping", [value]) => path.looping = value.parse().with_context(context)?,
+                ("duration", [value]) => duration = Some(value.parse::<f32>().with_context(context)?),
+                ("key", values) if values.len() == 7 => {
+                    let mut v = [0.0; 7];
+                    for (v, value) in v.iter_mut().zip(values) {
+                        *v = value.parse().with_context(context)?;
+                    }
+                    path.add(CameraKeyframe {
+                        time: v[0],
+                        position: Point3::new(v[1], v[2], v[3]),
+                        yaw: Rad(v[4]),
+                        pitch: Rad(v[5]),
+                        fovy: Rad(v[6]),
+                    });
+                }
+                _ => bail!("unexpected {}", context()),
+            }
+        }
+        if let Some(duration) = duration {
+            let last = path.keyframes.last().map_or(0.0, |k| k.time);
+            if duration < last {
+                return Err(anyhow!("duration {} ends before the last keyframe at {}", duration, last));
+            }
+            path.duration = duration;
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(time: f32, x: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            position: Point3::new(x, 0.0, 0.0),
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            fovy: Deg(45.0).into(),
+        }
+    }
+
+    fn path(interpolation: Interpolation) -> CameraPath {
+        let mut path = CameraPath::new();
+        path.interpolation = interpolation;
+        path.add(key(0.0, 0.0));
+        path.add(key(1.0, 4.0));
+        path.add(key(2.0, 2.0));
+        path.add(key(4.0, 6.0));
+        path
+    }
+
+    #[test]
+    fn passes_through_keyframes() {
+        for interpolation in [Interpolation::CatmullRom, Interpolation::Bezier] {
+            let path = path(interpolation);
+            for k in path.keyframes() {
+                let sample = path.sample(k.time).unwrap();
+                assert!((sample.position.x - k.position.x).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn bezier_does_not_overshoot() {
+        let path = path(Interpolation::Bezier);
+        for i in 0..=100 {
+            let x = path.sample(i as f32 * 0.01).unwrap().position.x;
+            assert!((0.0..=4.0 + 1e-5).contains(&x), "{}", x);
+        }
+    }
+
+    #[test]
+    fn clamps_without_looping() {
+        let path = path(Interpolation::CatmullRom);
+        assert_eq!(path.sample(-1.0).unwrap().position.x, 0.0);
+        assert_eq!(path.sample(10.0).unwrap().position.x, 6.0);
+    }
+
+    #[test]
+    fn looping_returns_to_start() {
+        let mut path = path(Interpolation::CatmullRom);
+        path.looping = true;
+        let period = path.duration;
+        assert!((path.sample(period).unwrap().position.x).abs() < 1e-5);
+        let a = path.sample(0.5).unwrap().position.x;
+        let b = path.sample(0.5 + period * 3.0).unwrap().position.x;
+        assert!((a - b).abs() < 1e-4);
+    }
+
+    #[test]
+    fn turntable_circles_the_target() {
+        let target = Point3::new(1.0, 2.0, 3.0);
+        let path = CameraPath::turntable(target, 5.0, Deg(-15.0).into(), Deg(45.0).into(), 4.0);
+        for i in 0..=40 {
+            let sample = path.sample(i as f32 * 0.1).unwrap();
+            let camera = Camera::new(sample.position, sample.yaw, sample.pitch);
+            // looking at the target from roughly the same distance all the way around
+            let to_target = target - sample.position;
+            assert!((to_target.magnitude() - 5.0).abs() < 0.05, "{}", to_target.magnitude());
+            assert!(to_target.normalize().dot(camera.forward()) > 0.999);
+        }
+        assert_eq!(path.sample(4.0).unwrap().yaw, Rad::full_turn());
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut path = path(Interpolation::Bezier);
+        path.looping = true;
+        let loaded = path.to_string().parse::<CameraPath>().unwrap();
+        assert_eq!(loaded.keyframes(), path.keyframes());
+        assert_eq!(loaded.interpolation, path.interpolation);
+        assert_eq!(loaded.looping, path.looping);
+        assert_eq!(loaded.duration, path.duration);
+        assert!("key 1 2 3".parse::<CameraPath>().is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use anyhow::{anyhow, bail, Context};
+use instant::Duration;
+
+// Rows copied out of a texture have to start on COPY_BYTES_PER_ROW_ALIGNMENT
+pub fn padded_bytes_per_row(width: u32, bytes_per_pixel: u32) -> u32 {
+    (width * bytes_per_pixel).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+}
+
+// Readback buffer for a single frame of a color texture
+pub struct FrameReadback {
+    buffer: wgpu::Buffer,
+    pub width: u32,
+    pub height: u32,
+    format: wgpu::TextureFormat,
+    padded_bytes_per_row: u32,
+}
+
+impl FrameReadback {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> anyhow::Result<Self> {
+        if !matches!(
+            format,
+            wgpu::TextureFormat::Rgba8Unorm
+                | wgpu::TextureFormat::Rgba8UnormSrgb
+                | wgpu::TextureFormat::Bgra8Unorm
+                | wgpu::TextureFormat::Bgra8UnormSrgb
+        ) {
+            bail!("can't read back {:?} frames, only 8 bit RGBA and BGRA", format);
+        }
+        let padded_bytes_per_row = padded_bytes_per_row(width, 4);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Readback Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Ok(Self {
+            buffer,
+            width,
+            height,
+            format,
+            padded_bytes_per_row,
+        })
+    }
+
+    // The texture needs COPY_SRC and has to match the readback size
+    pub fn copy(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    // Blocks until the copy has finished. Returns tightly packed RGBA8 rows.
+    pub fn read(&self, device: &wgpu::Device) -> anyhow::Result<Vec<u8>> {
+        let mapped = self.map();
+        device.poll(wgpu::Maintain::Wait);
+        mapped.recv()??;
+        Ok(self.mapped_pixels())
+    }
+
+    // Starts mapping after the copy was submitted, the receiver gets the
+    // result once the device has been polled past it
+    pub fn map(&self) -> Receiver<Result<(), wgpu::BufferAsyncError>> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        receiver
+    }
+
+    // Only valid once map() has succeeded, unmaps the buffer again
+    pub fn mapped_pixels(&self) -> Vec<u8> {
+        let row_bytes = (self.width * 4) as usize;
+        let mut pixels = Vec::with_capacity(row_bytes * self.height as usize);
+        {
+            let data = self.buffer.slice(..).get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..row_bytes]);
+            }
+        }
+        self.buffer.unmap();
+
+        if matches!(self.format, wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        pixels
+    }
+}
+
+// A single frame on its way to disk. The copy is mapped while the next frames
+// render, then encoded and written on a background thread.
+pub struct Screenshot {
+    readback: FrameReadback,
+    // set by submit_copy
+    mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+    path: PathBuf,
+}
+
+impl Screenshot {
+    // The texture needs COPY_SRC, call submit_copy once the encoder was submitted
+    pub fn new(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        texture: &wgpu::Texture,
+        path: PathBuf,
+    ) -> anyhow::Result<Self> {
+        let size = texture.size();
+        let readback = FrameReadback::new(device, size.width, size.height, texture.format())?;
+        readback.copy(encoder, texture);
+        Ok(Self { readback, mapped: None, path })
+    }
+
+    pub fn submit_copy(&mut self) {
+        self.mapped = Some(self.readback.map());
+    }
+
+    // Call once per frame after polling the device. Returns None once the
+    // screenshot has been handed to the writer thread.
+    pub fn update(self) -> Option<Self> {
+        let Some(mapped) = &self.mapped else {
+            return Some(self);
+        };
+        match mapped.try_recv() {
+            Err(TryRecvError::Empty) => Some(self),
+            Err(TryRecvError::Disconnected) => {
+                println!("Screenshot failed: readback was dropped");
+                None
+            }
+            Ok(Err(e)) => {
+                println!("Screenshot failed: {:?}", e);
+                None
+            }
+            Ok(Ok(())) => {
+                std::thread::spawn(move || {
+                    let pixels = self.readback.mapped_pixels();
+                    let (width, height) = (self.readback.width, self.readback.height);
+                    let saved = self.path.parent().map_or(Ok(()), std::fs::create_dir_all)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|_| Ok(image::save_buffer(&self.path, &pixels, width, height, image::ColorType::Rgba8)?));
+                    match saved {
+                        Ok(()) => println!("Saved screenshot to {}", self.path.display()),
+                        Err(e) => println!("Failed to save screenshot {}: {:?}", self.path.display(), e),
+                    }
+                });
+                None
+            }
+        }
+    }
+}
+
+// Frames for Renderer::start_render to capture, then the viewer exits
+pub struct RenderJob {
+    pub out: PathBuf,
+    pub camera_path: Option<PathBuf>,
+    pub frames: Option<u32>,
+    pub fps: f32,
+    pub encoder: Option<String>,
+}
+
+pub struct CaptureSettings {
+    pub directory: PathBuf,
+    pub fps: f32,
+    pub write_png: bool,
+    // Program and arguments that get raw RGBA frames on stdin. {width},
+    // {height} and {fps} are replaced, e.g.
+    // ffmpeg -f rawvideo -pix_fmt rgba -s {width}x{height} -r {fps} -i - out.mp4
+    pub encoder: Option<Vec<String>>,
+    // stop after this many frames
+    pub max_frames: Option<u32>,
+}
+
+impl Default for CaptureSettings {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("capture"),
+            fps: 60.0,
+            write_png: true,
+            encoder: None,
+            max_frames: None,
+        }
+    }
+}
+
+// Captures every rendered frame. The caller advances the scene by timestep()
+// per frame instead of wall-clock time, so two captures of the same scene
+// produce the same frames.
+pub struct FrameCapture {
+    settings: CaptureSettings,
+    readback: FrameReadback,
+    encoder: Option<Child>,
+    pub frame: u32,
+}
+
+impl FrameCapture {
+    pub fn new(
+        device: &wgpu::Device,
+        settings: CaptureSettings,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<Self> {
+        let readback = FrameReadback::new(device, width, height, format)?;
+        if settings.write_png {
+            std::fs::create_dir_all(&settings.directory)
+                .with_context(|| format!("creating {}", settings.directory.display()))?;
+        }
+        let encoder = match &settings.encoder {
+            Some(command) => {
+                let args = command.iter()
+                    .map(|arg| arg
+                        .replace("{width}", &width.to_string())
+                        .replace("{height}", &height.to_string())
+                        .replace("{fps}", &settings.fps.to_string()))
+                    .collect::<Vec<_>>();
+                let (program, args) = args.split_first().ok_or_else(|| anyhow!("empty encoder command"))?;
+                let child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("starting encoder {}", program))?;
+                Some(child)
+            }
+            None => None,
+        };
+        Ok(Self {
+            settings,
+            readback,
+            encoder,
+            frame: 0,
+        })
+    }
+
+    pub fn timestep(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.settings.fps)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.settings.max_frames.is_some_and(|max| self.frame >= max)
+    }
+
+    pub fn copy_frame(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) -> anyhow::Result<()> {
+        let size = texture.size();
+        if size.width != self.readback.width || size.height != self.readback.height {
+            bail!(
+                "frame size changed from {}x{} to {}x{} during capture",
+                self.readback.width, self.readback.height, size.width, size.height,
+            );
+        }
+        self.readback.copy(encoder, texture);
+        Ok(())
+    }
+
+    // Has to be called after the encoder holding copy_frame was submitted
+    pub fn write_frame(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        let pixels = self.readback.read(device)?;
+        if self.settings.write_png {
+            let path = self.settings.directory.join(format!("frame_{:05}.png", self.frame));
+            image::save_buffer(&path, &pixels, self.readback.width, self.readback.height, image::ColorType::Rgba8)
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+        if let Some(stdin) = self.encoder.as_mut().and_then(|child| child.stdin.as_mut()) {
+            stdin.write_all(&pixels).context("writing to encoder")?;
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    // Closes the encoder's stdin and waits for it to finish the file
+    pub fn finish(mut self) -> anyhow::Result<u32> {
+        if let Some(mut child) = self.encoder.take() {
+            drop(child.stdin.take());
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("encoder exited with {}", status);
+            }
+        }
+        Ok(self.frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_are_padded_to_copy_alignment() {
+        assert_eq!(padded_bytes_per_row(64, 4), 256);
+        assert_eq!(padded_bytes_per_row(65, 4), 512);
+        assert_eq!(padded_bytes_per_row(1, 4), 256);
+    }
+}
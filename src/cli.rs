@@ -0,0 +1,245 @@
+use std::path::{Path, PathBuf};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use wgpu_testing::capture::RenderJob;
+use wgpu_testing::input_map::InputMap;
+use wgpu_testing::resources::{load_model_info, ModelInfo, ModelPath};
+use wgpu_testing::RendererOptions;
+
+#[derive(Parser, Debug)]
+#[command(about = "wgpu model viewer")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub window: WindowOptions,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Open a model in the viewer, the default when no command is given
+    View {
+        /// .obj file, either a path on disk or relative to res/
+        model: Option<PathBuf>,
+    },
+    /// Render a turntable or camera path of a model to numbered PNGs
+    Render {
+        /// .obj file, either a path on disk or relative to res/
+        scene: PathBuf,
+        /// Directory the frames are written to
+        #[arg(long)]
+        out: PathBuf,
+        /// Camera path to play instead of a turntable
+        #[arg(long)]
+        camera_path: Option<PathBuf>,
+        /// Number of frames, defaults to the camera path's length or 120
+        #[arg(long)]
+        frames: Option<u32>,
+        #[arg(long, default_value_t = 30.0)]
+        fps: f32,
+        /// Command that gets raw RGBA frames on stdin, see CaptureSettings::encoder
+        #[arg(long)]
+        encoder: Option<String>,
+    },
+    /// Print mesh and material statistics, doesn't need a GPU
+    Info {
+        model: PathBuf,
+    },
+    /// Report missing material files and textures, exits with 1 if any are missing
+    Validate {
+        model: PathBuf,
+    },
+    /// Print the input map, a file's bindings over the defaults if given,
+    /// exits with 1 if two actions share a binding
+    Bindings {
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct WindowOptions {
+    /// Graphics API, all available ones are tried when not set
+    #[arg(long, global = true, value_enum)]
+    pub backend: Option<Backend>,
+    /// Window size as WIDTHxHEIGHT, also the size of rendered frames
+    #[arg(long, global = true, value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+    #[arg(long, global = true)]
+    pub fullscreen: bool,
+    /// on waits for vblank (Fifo), off presents immediately and uncaps the frame rate
+    #[arg(long, global = true, value_enum, default_value_t = Vsync::On)]
+    pub vsync: Vsync,
+    /// Input map loaded over the default bindings, input_map.txt when it exists
+    #[arg(long, global = true)]
+    pub bindings: Option<PathBuf>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Vulkan,
+    Gl,
+    Dx12,
+    Metal,
+}
+
+impl WindowOptions {
+    pub fn renderer_options(&self) -> RendererOptions {
+        RendererOptions {
+            backends: self.backend.map_or(wgpu::Backends::all(), Backend::backends),
+            vsync: self.vsync == Vsync::On,
+            bindings: self.bindings.clone(),
+        }
+    }
+}
+
+impl Backend {
+    pub fn backends(self) -> wgpu::Backends {
+        match self {
+            Self::Vulkan => wgpu::Backends::VULKAN,
+            Self::Gl => wgpu::Backends::GL,
+            Self::Dx12 => wgpu::Backends::DX12,
+            Self::Metal => wgpu::Backends::METAL,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Vsync {
+    #[default]
+    On,
+    Off,
+}
+
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let parse = |value: &str| value.trim().parse::<u32>().ok().filter(|&value| value > 0);
+    size.split_once(['x', 'X'])
+        .and_then(|(width, height)| Some((parse(width)?, parse(height)?)))
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {}", size))
+}
+
+pub struct RunOptions {
+    pub model: ModelPath,
+    pub window: WindowOptions,
+    pub render: Option<RenderJob>,
+}
+
+pub fn print_info(model: &Path) -> anyhow::Result<()> {
+    let path = ModelPath::new(model);
+    let info = load_model_info(&path.file_name, &path.subfolder)?;
+    println!("{}", model.display());
+    println!(
+        "  {} meshes, {} vertices, {} triangles, {} materials",
+        info.meshes.len(),
+        info.meshes.iter().map(|m| m.vertices).sum::<usize>(),
+        info.meshes.iter().map(|m| m.triangles).sum::<usize>(),
+        info.materials.len(),
+    );
+    if let Some(aabb) = info.aabb {
+        let size = aabb.max - aabb.min;
+        println!(
+            "  bounds ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3}), size {:.3} x {:.3} x {:.3}",
+            aabb.min.x, aabb.min.y, aabb.min.z, aabb.max.x, aabb.max.y, aabb.max.z, size.x, size.y, size.z,
+        );
+    }
+    println!("meshes:");
+    for mesh in &info.meshes {
+        let material = mesh.material
+            .and_then(|i| info.materials.get(i))
+            .map_or("none", |m| m.name.as_str());
+        println!(
+            "  {}: {} vertices, {} triangles, material {}{}{}",
+            mesh.name,
+            mesh.vertices,
+            mesh.triangles,
+            material,
+            if mesh.has_normals { "" } else { ", no normals" },
+            if mesh.has_tex_coords { "" } else { ", no texture coordinates" },
+        );
+    }
+    println!("materials:");
+    for material in &info.materials {
+        println!(
+            "  {}: diffuse {}, normal {}",
+            material.name,
+            material.diffuse_texture.as_deref().unwrap_or("none"),
+            material.normal_texture.as_deref().unwrap_or("none"),
+        );
+    }
+    Ok(())
+}
+
+// Returns false if anything is missing
+pub fn validate(model: &Path) -> anyhow::Result<bool> {
+    let path = ModelPath::new(model);
+    let info = load_model_info(&path.file_name, &path.subfolder)?;
+    let problems = validation_problems(&info);
+    if problems.is_empty() {
+        println!("{}: ok", model.display());
+    } else {
+        println!("{}: {} problems", model.display(), problems.len());
+        for problem in &problems {
+            println!("  {}", problem);
+        }
+    }
+    Ok(problems.is_empty())
+}
+
+// The output is itself an input map file, conflicts are listed as comments
+pub fn print_bindings(file: Option<&Path>) -> anyhow::Result<bool> {
+    let map = match file {
+        Some(file) => InputMap::load(file)?,
+        None => InputMap::default(),
+    };
+    print!("{}", map);
+    let conflicts = map.conflicts();
+    for conflict in &conflicts {
+        println!("# conflict: {}", conflict);
+    }
+    Ok(conflicts.is_empty())
+}
+
+fn validation_problems(info: &ModelInfo) -> Vec<String> {
+    let mut problems = info.missing_files.iter()
+        .map(|file| format!("missing file {}", file.display()))
+        .collect::<Vec<_>>();
+    for mesh in &info.meshes {
+        // load_model_obj reads both for every vertex
+        if !mesh.has_normals {
+            problems.push(format!("mesh {} has no normals", mesh.name));
+        }
+        if !mesh.has_tex_coords {
+            problems.push(format!("mesh {} has no texture coordinates", mesh.name));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("1280x720"), Ok((1280, 720)));
+        assert_eq!(parse_size("640X480"), Ok((640, 480)));
+        assert!(parse_size("1280").is_err());
+        assert!(parse_size("0x720").is_err());
+    }
+
+    #[test]
+    fn parses_commands_and_global_flags() {
+        let cli = Cli::try_parse_from(["viewer", "render", "cube/cube.obj", "--out", "frames", "--size", "64x64", "--vsync", "off"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Render { ref out, .. }) if out == Path::new("frames")));
+        assert_eq!(cli.window.size, Some((64, 64)));
+        assert_eq!(cli.window.vsync, Vsync::Off);
+        assert!(Cli::try_parse_from(["viewer", "--backend", "directx"]).is_err());
+    }
+
+    #[test]
+    fn cube_is_valid() {
+        let info = load_model_info("cube.obj", "cube").unwrap();
+        assert!(validation_problems(&info).is_empty());
+        assert_eq!(info.meshes.len(), 1);
+        assert_eq!(info.meshes[0].triangles, 428);
+    }
+}
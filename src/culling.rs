@@ -0,0 +1,232 @@
+use std::ops::Range;
+use cgmath::*;
+
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::camera::{Camera, Projection};
+use crate::instancing::InstanceRaw;
+use crate::model::Model;
+
+// Points p with normal.dot(p) + distance >= 0 are on the inside
+#[derive(Copy, Clone, Debug)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_row(row: Vector4<f32>) -> Self {
+        let length = row.truncate().magnitude();
+        // the far plane of an infinite projection, everything is inside
+        if length <= f32::EPSILON {
+            return Self {
+                normal: Vector3::zero(),
+                distance: f32::MAX,
+            };
+        }
+        Self {
+            normal: row.truncate() / length,
+            distance: row.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    // left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction, using wgpu's 0..1 clip space depth range.
+    // With reverse-z the near and far planes swap places, which doesn't matter
+    // for testing against all six.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+        Self {
+            planes: [
+                Plane::from_row(r3 + r0),
+                Plane::from_row(r3 - r0),
+                Plane::from_row(r3 + r1),
+                Plane::from_row(r3 - r1),
+                Plane::from_row(r2),
+                Plane::from_row(r3 - r2),
+            ],
+        }
+    }
+
+    pub fn new(camera: &Camera, projection: &Projection) -> Self {
+        Self::from_matrix(projection.calc_matrix() * camera.calc_matrix())
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let positive = Point3::new(
+                if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(positive) >= 0.0
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    Off,
+    Cpu,
+    Gpu,
+}
+
+impl CullMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Cpu,
+            Self::Cpu => Self::Gpu,
+            Self::Gpu => Self::Off,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CullStats {
+    pub instances_drawn: u32,
+    pub instances_culled: u32,
+    pub meshes_drawn: u32,
+    pub meshes_culled: u32,
+}
+
+// Instance data regrouped per mesh. Mesh i draws mesh_instances[i] out of
+// instances, which is uploaded as the instance buffer.
+pub struct CullResult {
+    pub instances: Vec<InstanceRaw>,
+    pub mesh_instances: Vec<Range<u32>>,
+    pub stats: CullStats,
+}
+
+pub fn cull_model(
+    frustum: &Frustum,
+    model: &Model,
+    instances: &[InstanceRaw],
+) -> CullResult {
+    let mut stats = CullStats::default();
+    let model_sphere = model.bounding_sphere();
+
+    let visible = instances.iter()
+        .filter_map(|raw| {
+            let matrix = Matrix4::from(raw.model);
+            if frustum.intersects_sphere(&model_sphere.transform(&matrix)) {
+                stats.instances_drawn += 1;
+                Some((raw, matrix))
+            } else {
+                stats.instances_culled += 1;
+                stats.meshes_culled += model.meshes.len() as u32;
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut result = Vec::new();
+    let mut mesh_instances = Vec::with_capacity(model.meshes.len());
+    for mesh in &model.meshes {
+        let start = result.len() as u32;
+        for (raw, matrix) in &visible {
+            // sphere first since it's cheaper, then the tighter box
+            if frustum.intersects_sphere(&mesh.bounding_sphere.transform(matrix))
+                && frustum.intersects_aabb(&mesh.aabb.transform(matrix)) {
+                stats.meshes_drawn += 1;
+                result.push(**raw);
+            } else {
+                stats.meshes_culled += 1;
+            }
+        }
+        mesh_instances.push(start..result.len() as u32);
+    }
+
+    CullResult {
+        instances: result,
+        mesh_instances,
+        stats,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::DepthMode;
+
+    fn frustum() -> Frustum {
+        // at the origin looking down -z
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0));
+        let projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        Frustum::new(&camera, &projection)
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere { center: Point3::new(x, y, z), radius }
+    }
+
+    #[test]
+    fn sphere_in_front_is_visible() {
+        assert!(frustum().intersects_sphere(&sphere(0.0, 0.0, -10.0, 1.0)));
+    }
+
+    #[test]
+    fn sphere_behind_is_culled() {
+        assert!(!frustum().intersects_sphere(&sphere(0.0, 0.0, 10.0, 1.0)));
+    }
+
+    #[test]
+    fn sphere_past_far_plane_is_culled() {
+        let frustum = frustum();
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -102.0, 1.0)));
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -100.5, 1.0)));
+    }
+
+    #[test]
+    fn sphere_outside_side_planes_is_culled() {
+        let frustum = frustum();
+        assert!(!frustum.intersects_sphere(&sphere(-50.0, 0.0, -10.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 50.0, -10.0, 1.0)));
+    }
+
+    #[test]
+    fn sphere_straddling_a_plane_is_visible() {
+        // left plane at z = -10 is roughly x = -5.5 for a 45 degree fov at 4:3
+        assert!(frustum().intersects_sphere(&sphere(-6.0, 0.0, -10.0, 1.0)));
+    }
+
+    #[test]
+    fn reverse_z_has_no_far_plane() {
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(0.0));
+        let mut projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        projection.set_depth_mode(DepthMode::ReverseZ);
+        let frustum = Frustum::new(&camera, &projection);
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -10.0, 1.0)));
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -10000.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 10.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(-50.0, 0.0, -10.0, 1.0)));
+    }
+
+    #[test]
+    fn aabb_tests() {
+        let frustum = frustum();
+        let inside = Aabb { min: Point3::new(-1.0, -1.0, -11.0), max: Point3::new(1.0, 1.0, -9.0) };
+        let behind = Aabb { min: Point3::new(-1.0, -1.0, 9.0), max: Point3::new(1.0, 1.0, 11.0) };
+        let around_camera = Aabb { min: Point3::new(-1.0, -1.0, -1.0), max: Point3::new(1.0, 1.0, 1.0) };
+        assert!(frustum.intersects_aabb(&inside));
+        assert!(!frustum.intersects_aabb(&behind));
+        assert!(frustum.intersects_aabb(&around_camera));
+    }
+}
@@ -0,0 +1,189 @@
+use cgmath::*;
+
+use crate::bounds::Aabb;
+use crate::camera::DepthMode;
+use crate::debug_view::create_overlay_pipeline;
+use crate::model::Vertex;
+
+const SPHERE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl Vertex for DebugVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+// Immediate mode line drawing. Shapes are queued during update, then uploaded
+// into one vertex buffer and drawn with a single call at the end of the frame.
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+    buffer: wgpu::Buffer,
+    // in vertices
+    capacity: usize,
+    num_vertices: u32,
+    // indexed by DepthMode
+    pipeline: [wgpu::RenderPipeline; 2],
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::with_depth_test(device, color_format, camera_bind_group_layout, true)
+    }
+
+    // Lines that are never hidden by the scene, for handles the user has to reach
+    pub fn on_top(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self::with_depth_test(device, color_format, camera_bind_group_layout, false)
+    }
+
+    fn with_depth_test(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        depth_test: bool,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = DepthMode::ALL.map(|depth_mode| create_overlay_pipeline(
+            device,
+            &layout,
+            color_format,
+            &[DebugVertex::desc()],
+            wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            "fs_main",
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Debug Lines Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("debug_lines.wgsl").into()),
+            },
+            depth_mode,
+            if depth_test { depth_mode.compare_equal() } else { wgpu::CompareFunction::Always },
+        ));
+        let capacity = 1024;
+
+        Self {
+            vertices: Vec::with_capacity(capacity),
+            buffer: Self::create_buffer(device, capacity),
+            capacity,
+            num_vertices: 0,
+            pipeline,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Draw Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 3]) {
+        self.vertices.push(DebugVertex { position: a.into(), color });
+        self.vertices.push(DebugVertex { position: b.into(), color });
+    }
+
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, color: [f32; 3]) {
+        let c = Aabb { min, max }.corners();
+        for i in 0..4 {
+            // bottom face, top face, then the four verticals
+            self.line(c[i], c[(i + 1) % 4], color);
+            self.line(c[i + 4], c[(i + 1) % 4 + 4], color);
+            self.line(c[i], c[i + 4], color);
+        }
+    }
+
+    // Circle around `normal`, which needs to be unit length
+    pub fn circle(&mut self, center: Point3<f32>, normal: Vector3<f32>, radius: f32, color: [f32; 3]) {
+        let helper = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+        let u = normal.cross(helper).normalize() * radius;
+        let v = normal.cross(u);
+        let point = |angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            center + u * cos + v * sin
+        };
+        let step = std::f32::consts::TAU / SPHERE_SEGMENTS as f32;
+        for i in 0..SPHERE_SEGMENTS {
+            self.line(point(i as f32 * step), point((i + 1) as f32 * step), color);
+        }
+    }
+
+    // Three great circles, one per axis plane
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 3]) {
+        self.circle(center, Vector3::unit_x(), radius, color);
+        self.circle(center, Vector3::unit_y(), radius, color);
+        self.circle(center, Vector3::unit_z(), radius, color);
+    }
+
+    pub fn axes(&mut self, origin: Point3<f32>, size: f32) {
+        self.line(origin, origin + Vector3::unit_x() * size, [1.0, 0.0, 0.0]);
+        self.line(origin, origin + Vector3::unit_y() * size, [0.0, 1.0, 0.0]);
+        self.line(origin, origin + Vector3::unit_z() * size, [0.0, 0.0, 1.0]);
+    }
+
+    // Copies everything queued since the last clear to the GPU, growing the
+    // buffer if this frame drew more than it can hold
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.vertices.len() > self.capacity {
+            self.capacity = self.vertices.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.vertices));
+        self.num_vertices = self.vertices.len() as u32;
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        depth_mode: DepthMode,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.num_vertices == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline[depth_mode as usize]);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.num_vertices, 0..1);
+    }
+}
@@ -0,0 +1,219 @@
+use std::ops::Range;
+
+use crate::camera::DepthMode;
+use crate::input_map::Action;
+use crate::instancing::InstanceRaw;
+use crate::model::{Model, ModelVertex, Vertex};
+use crate::texture;
+
+// The discriminants are read by shader.wgsl through DataUniform::debug_view
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    Shaded = 0,
+    Wireframe = 1,
+    Normals = 2,
+    Tangents = 3,
+    UvChecker = 4,
+    MaterialId = 5,
+}
+
+impl DebugView {
+    pub fn from_action(action: Action) -> Option<Self> {
+        match action {
+            Action::DebugShaded => Some(Self::Shaded),
+            Action::DebugWireframe => Some(Self::Wireframe),
+            Action::DebugNormals => Some(Self::Normals),
+            Action::DebugTangents => Some(Self::Tangents),
+            Action::DebugUvChecker => Some(Self::UvChecker),
+            Action::DebugMaterialId => Some(Self::MaterialId),
+            _ => None,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn create_overlay_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    primitive: wgpu::PrimitiveState,
+    fragment_entry_point: &str,
+    shader: wgpu::ShaderModuleDescriptor,
+    depth_mode: DepthMode,
+    depth_compare: wgpu::CompareFunction,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(shader);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Debug Overlay Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive,
+        // Overlays are tested against the shaded pass with depth_compare but
+        // never write depth, the bias pulls the lines in front of the faces
+        // they were drawn from
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: depth_mode.bias_towards_camera(2, 1.0),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// Pipelines are indexed by DepthMode
+pub struct DebugViewRenderer {
+    wireframe_pipeline: [wgpu::RenderPipeline; 2],
+    // true when the device lacks POLYGON_MODE_LINE and the wireframe is
+    // drawn from Mesh::wireframe_vertex_buffer instead
+    barycentric_wireframe: bool,
+    tangent_pipeline: [wgpu::RenderPipeline; 2],
+}
+
+impl DebugViewRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug View Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let barycentric_wireframe = !device.features().contains(wgpu::Features::POLYGON_MODE_LINE);
+        let wireframe_pipeline = DepthMode::ALL.map(|depth_mode| create_overlay_pipeline(
+            device,
+            &layout,
+            color_format,
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: if barycentric_wireframe {
+                    wgpu::PolygonMode::Fill
+                } else {
+                    wgpu::PolygonMode::Line
+                },
+                ..Default::default()
+            },
+            if barycentric_wireframe { "fs_barycentric" } else { "fs_main" },
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Wireframe Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("wireframe.wgsl").into()),
+            },
+            depth_mode,
+            depth_mode.compare_equal(),
+        ));
+
+        // One instance per model vertex, and a zero stride on the instance
+        // buffer so a single InstanceRaw is shared by all of them
+        let tangent_pipeline = DepthMode::ALL.map(|depth_mode| create_overlay_pipeline(
+            device,
+            &layout,
+            color_format,
+            &[
+                wgpu::VertexBufferLayout {
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    ..ModelVertex::desc()
+                },
+                wgpu::VertexBufferLayout {
+                    array_stride: 0,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    ..InstanceRaw::desc()
+                },
+            ],
+            wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            "fs_main",
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Tangent Lines Shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("tangent_lines.wgsl").into()),
+            },
+            depth_mode,
+            depth_mode.compare_equal(),
+        ));
+
+        Self {
+            wireframe_pipeline,
+            barycentric_wireframe,
+            tangent_pipeline,
+        }
+    }
+
+    // Draws whatever the view needs on top of the shaded model. Views that are
+    // handled entirely in shader.wgsl draw nothing here.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        view: DebugView,
+        depth_mode: DepthMode,
+        model: &'a Model,
+        instance_buffer: &'a wgpu::Buffer,
+        instances: Range<u32>,
+        bind_groups: &[&'a wgpu::BindGroup],
+    ) {
+        match view {
+            DebugView::Wireframe => {
+                render_pass.set_pipeline(&self.wireframe_pipeline[depth_mode as usize]);
+                for (i, bind_group) in bind_groups.iter().enumerate() {
+                    render_pass.set_bind_group(i as u32, bind_group, &[]);
+                }
+                render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+                for mesh in &model.meshes {
+                    match (&mesh.wireframe_vertex_buffer, self.barycentric_wireframe) {
+                        (Some(buffer), true) => {
+                            render_pass.set_vertex_buffer(0, buffer.slice(..));
+                            render_pass.draw(0..mesh.num_elements, instances.clone());
+                        }
+                        _ => {
+                            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                            render_pass.draw_indexed(0..mesh.num_elements, 0, instances.clone());
+                        }
+                    }
+                }
+            }
+            DebugView::Tangents => {
+                render_pass.set_pipeline(&self.tangent_pipeline[depth_mode as usize]);
+                for (i, bind_group) in bind_groups.iter().enumerate() {
+                    render_pass.set_bind_group(i as u32, bind_group, &[]);
+                }
+                let stride = std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+                for instance in instances {
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(instance as u64 * stride..));
+                    for mesh in &model.meshes {
+                        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                        render_pass.draw(0..6, 0..mesh.num_vertices);
+                    }
+                }
+            }
+            DebugView::Shaded | DebugView::Normals | DebugView::UvChecker | DebugView::MaterialId => {}
+        }
+    }
+}
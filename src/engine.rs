@@ -1,17 +1,34 @@
-use crate::state::State;
+use wgpu_testing::capture::RenderJob;
+use wgpu_testing::input_map::Action;
+use wgpu_testing::Renderer;
+
+use crate::cli::RunOptions;
 
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::WindowBuilder,
+    dpi::PhysicalSize,
+    window::{Fullscreen, WindowBuilder},
 };
 
-const FRAMERATE_CAP: f32 = 144.0;
-const FRAMETIME_CAP: f32 = 1.0/FRAMERATE_CAP;
-pub async fn run(){
+// Frames for the render command are drawn offscreen at --size
+const DEFAULT_RENDER_SIZE: (u32, u32) = (1280, 720);
+
+pub async fn run(options: RunOptions) {
     env_logger::init();
+    if let Some(job) = &options.render {
+        render(&options, job).await;
+        return;
+    }
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
+    let mut builder = WindowBuilder::new().with_title("wgpu-testing");
+    if let Some((width, height)) = options.window.size {
+        builder = builder.with_inner_size(PhysicalSize::new(width, height));
+    }
+    if options.window.fullscreen {
+        builder = builder.with_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+    let window = builder.build(&event_loop).unwrap();
 
     let mut imgui = imgui::Context::create();
     let mut platform = imgui_winit_support::WinitPlatform::init(&mut imgui);
@@ -25,66 +42,93 @@ pub async fn run(){
     let hidpi_factor = window.scale_factor();
     imgui.io_mut().font_global_scale = (1.0 / hidpi_factor) as f32;
 
-    let mut state = State::new(window).await;
+    let main_window = window.id();
+    let mut state = Renderer::new(window, &options.model, &options.window.renderer_options()).await;
     let mut last_render_time = instant::Instant::now();
 
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run(move |event, event_loop, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion{ delta, },
                 .. // We're not using device_id currently
-            } => if state.mouse_pressed {
-                state.camera_controller.process_mouse(delta.0, delta.1)
+            } => {
+                state.process_mouse_motion(delta.0, delta.1)
             }
-            Event::WindowEvent { ref event, window_id } if window_id == state.window.id() => {
-                if !state.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested
-                        | WindowEvent::KeyboardInput {
-                            input: KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                            ..
-                        } => *control_flow = ControlFlow::Exit,
-                        WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
-                        }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            state.resize(**new_inner_size);
+            Event::WindowEvent { ref event, window_id } if window_id == main_window && !state.input(event) => {
+                let (actions, pressed) = state.event_actions(event);
+                let action = |action| pressed && actions.contains(&action);
+                match event {
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                    _ if action(Action::Quit) => *control_flow = ControlFlow::Exit,
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(physical_size.width, physical_size.height);
+                    }
+                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        state.resize(new_inner_size.width, new_inner_size.height);
+                    }
+                    // another view onto the scene with its own camera
+                    _ if action(Action::NewWindow) => {
+                        let title = format!("wgpu-testing view {}", state.windows.len() + 2);
+                        let opened = WindowBuilder::new().with_title(title).build(event_loop)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|window| state.open_window(window));
+                        if let Err(e) = opened {
+                            println!("Failed to open window: {:?}", e);
                         }
-                        _ => {}
                     }
+                    _ => {}
+                }
+            }
+            Event::WindowEvent { ref event, window_id } if state.has_window(window_id) && !state.window_input(window_id, event) => {
+                let (actions, pressed) = state.window_event_actions(window_id, event);
+                if matches!(event, WindowEvent::CloseRequested) || pressed && actions.contains(&Action::Quit) {
+                    state.close_window(window_id);
                 }
             }
-            Event::RedrawRequested(window_id) if window_id == state.window().id() => {
+            Event::RedrawRequested(window_id) if window_id == main_window => {
                 let now = instant::Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
-                state.update(dt);
+                state.update(state.frame_timestep().unwrap_or(dt));
 
                 match state.render() {
                     Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                    Err(wgpu::SurfaceError::Lost) => {
+                        let (width, height) = state.target.size();
+                        state.resize(width, height);
+                    }
                     Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
                     Err(e) => eprintln!("{:?}", e),
                 }
-                // wait until delta time is 1/FRAMERATE_CAP
-                // TODO: still not fully sure that this is working, double check later
-                if dt.as_secs_f32() < FRAMETIME_CAP {
-                    std::thread::sleep(
-                        std::time::Duration::from_micros(
-                            ((FRAMETIME_CAP - dt.as_secs_f32()) * 1000000.0) as u64
-                        )
-                    );
+                // captures run as fast as they can, their timestep is fixed anyway
+                if state.capture.is_none() {
+                    state.frame_limiter.wait();
                 }
             }
             Event::MainEventsCleared => {
-                state.window.request_redraw();
+                if let Some(window) = state.window() {
+                    window.request_redraw();
+                }
             }
             _ => {}
         }
     });
 }
+
+// Draws the render command's frames offscreen, as fast as they can be written
+async fn render(options: &RunOptions, job: &RenderJob) {
+    let (width, height) = options.window.size.unwrap_or(DEFAULT_RENDER_SIZE);
+    let mut state = Renderer::headless(width, height, &options.model, &options.window.renderer_options()).await;
+    if let Err(e) = state.start_render(job) {
+        eprintln!("Render failed: {:?}", e);
+        std::process::exit(1);
+    }
+    while let Some(timestep) = state.frame_timestep() {
+        state.update(timestep);
+        if let Err(e) = state.render() {
+            eprintln!("Render failed: {:?}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use instant::{Duration, Instant};
+
+// Frame rate caps cycled at runtime, None is uncapped
+pub const FRAMERATE_CAPS: [Option<f32>; 5] = [None, Some(30.0), Some(60.0), Some(144.0), Some(240.0)];
+
+// sleep() overshoots by up to a scheduler tick, so the last stretch is spun
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+// Paces frames against a fixed cadence. Each wait() ends one target frame
+// time after the previous one ended, so however long the current frame's
+// update and render took is subtracted, not the previous frame's.
+pub struct FrameLimiter {
+    fps: Option<f32>,
+    target: Option<Duration>,
+    frame_start: Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(fps: Option<f32>) -> Self {
+        Self {
+            fps,
+            target: fps.map(|fps| Duration::from_secs_f32(1.0 / fps)),
+            frame_start: Instant::now(),
+        }
+    }
+
+    pub fn fps(&self) -> Option<f32> {
+        self.fps
+    }
+
+    pub fn set_fps(&mut self, fps: Option<f32>) {
+        *self = Self::new(fps);
+    }
+
+    // Steps through FRAMERATE_CAPS
+    pub fn next_cap(&mut self) {
+        let current = FRAMERATE_CAPS.iter().position(|&cap| cap == self.fps()).unwrap_or(0);
+        self.set_fps(FRAMERATE_CAPS[(current + 1) % FRAMERATE_CAPS.len()]);
+    }
+
+    // Call once per frame after presenting
+    pub fn wait(&mut self) {
+        let Some(target) = self.target else {
+            self.frame_start = Instant::now();
+            return;
+        };
+        let deadline = self.frame_start + target;
+        let now = Instant::now();
+        if now >= deadline {
+            // missed it, start a new cadence instead of trying to catch up
+            self.frame_start = now;
+            return;
+        }
+        let remaining = deadline - now;
+        if remaining > SPIN_MARGIN {
+            std::thread::sleep(remaining - SPIN_MARGIN);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+        self.frame_start = deadline;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_out_the_rest_of_each_frame() {
+        let mut limiter = FrameLimiter::new(Some(200.0));
+        let start = Instant::now();
+        for _ in 0..10 {
+            limiter.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(45));
+    }
+
+    #[test]
+    fn cycles_caps() {
+        let mut limiter = FrameLimiter::new(None);
+        for cap in FRAMERATE_CAPS.iter().skip(1).chain(&FRAMERATE_CAPS[..1]) {
+            limiter.next_cap();
+            assert_eq!(limiter.fps(), *cap);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use cgmath::*;
+
+use crate::debug_draw::DebugDraw;
+use crate::input_map::Action;
+use crate::instancing::Instance;
+use crate::raycast::Ray;
+
+// Increments while snapping is held
+pub const TRANSLATE_SNAP: f32 = 0.25;
+pub const ROTATE_SNAP: Deg<f32> = Deg(15.0);
+pub const SCALE_SNAP: f32 = 0.1;
+
+// Handle sizes as a fraction of the gizmo size, which is the axis length
+const PICK_RADIUS: f32 = 0.08;
+const PLANE_MIN: f32 = 0.25;
+const PLANE_MAX: f32 = 0.45;
+const TIP_SIZE: f32 = 0.05;
+const MIN_SCALE: f32 = 0.01;
+
+const AXIS_COLORS: [[f32; 3]; 3] = [[1.0, 0.25, 0.25], [0.25, 1.0, 0.25], [0.3, 0.45, 1.0]];
+const HOVER_COLOR: [f32; 3] = [1.0, 1.0, 0.3];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GizmoMode {
+    Translate,
+    Rotate,
+    Scale,
+}
+
+impl GizmoMode {
+    pub fn from_action(action: Action) -> Option<Self> {
+        match action {
+            Action::GizmoTranslate => Some(Self::Translate),
+            Action::GizmoRotate => Some(Self::Rotate),
+            Action::GizmoScale => Some(Self::Scale),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GizmoSpace {
+    World,
+    Local,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Handle {
+    // Along one axis, or around it when rotating
+    Axis(usize),
+    // In the plane of the other two axes, named by its normal
+    Plane(usize),
+}
+
+// A finished drag, kept so it can be undone
+#[derive(Clone, Debug)]
+pub struct TransformEdit {
+    pub instance: usize,
+    pub before: Instance,
+    pub after: Instance,
+}
+
+struct Drag {
+    handle: Handle,
+    instance: usize,
+    before: Instance,
+    axes: [Vector3<f32>; 3],
+    // where the drag grabbed the handle's line or plane
+    start: Point3<f32>,
+}
+
+pub struct Gizmo {
+    // None hides the gizmo
+    pub mode: Option<GizmoMode>,
+    pub space: GizmoSpace,
+    pub hovered: Option<Handle>,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn new() -> Self {
+        Self {
+            mode: None,
+            space: GizmoSpace::World,
+            hovered: None,
+            drag: None,
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn cancel(&mut self) {
+        self.drag = None;
+        self.hovered = None;
+    }
+
+    // Scaling is only defined along the instance's own axes
+    fn axes(&self, instance: &Instance) -> [Vector3<f32>; 3] {
+        let local = self.space == GizmoSpace::Local || self.mode == Some(GizmoMode::Scale);
+        let unit = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        if local {
+            unit.map(|axis| instance.rotation.rotate_vector(axis))
+        } else {
+            unit
+        }
+    }
+
+    fn handles(&self) -> &'static [Handle] {
+        use Handle::*;
+        match self.mode {
+            Some(GizmoMode::Rotate) => &[Axis(0), Axis(1), Axis(2)],
+            Some(_) => &[Axis(0), Axis(1), Axis(2), Plane(0), Plane(1), Plane(2)],
+            None => &[],
+        }
+    }
+
+    pub fn draw(&self, debug: &mut DebugDraw, instance: &Instance, size: f32) {
+        let Some(mode) = self.mode else { return };
+        let center = Point3::from_vec(instance.position);
+        let axes = self.drag.as_ref().map_or_else(|| self.axes(instance), |drag| drag.axes);
+        let active = self.drag.as_ref().map(|drag| drag.handle).or(self.hovered);
+        let color = |handle: Handle, index: usize| {
+            if active == Some(handle) { HOVER_COLOR } else { AXIS_COLORS[index] }
+        };
+
+        for (i, &axis) in axes.iter().enumerate() {
+            let tip = center + axis * size;
+            match mode {
+                GizmoMode::Rotate => debug.circle(center, axis, size, color(Handle::Axis(i), i)),
+                GizmoMode::Translate => {
+                    debug.line(center, tip, color(Handle::Axis(i), i));
+                    // arrow head in the plane of the next axis
+                    let side = axes[(i + 1) % 3] * size * TIP_SIZE;
+                    let back = tip - axis * size * TIP_SIZE * 2.0;
+                    debug.line(tip, back + side, color(Handle::Axis(i), i));
+                    debug.line(tip, back - side, color(Handle::Axis(i), i));
+                }
+                GizmoMode::Scale => {
+                    debug.line(center, tip, color(Handle::Axis(i), i));
+                    let half = Vector3::new(1.0, 1.0, 1.0) * size * TIP_SIZE;
+                    debug.aabb(tip - half, tip + half, color(Handle::Axis(i), i));
+                }
+            }
+            if mode != GizmoMode::Rotate {
+                let (u, v) = (axes[(i + 1) % 3] * size, axes[(i + 2) % 3] * size);
+                let corners = [(PLANE_MIN, PLANE_MIN), (PLANE_MAX, PLANE_MIN), (PLANE_MAX, PLANE_MAX), (PLANE_MIN, PLANE_MAX)]
+                    .map(|(a, b)| center + u * a + v * b);
+                for c in 0..4 {
+                    debug.line(corners[c], corners[(c + 1) % 4], color(Handle::Plane(i), i));
+                }
+            }
+        }
+    }
+
+    // The handle under the ray, closest to its origin
+    pub fn hit(&self, ray: &Ray, instance: &Instance, size: f32) -> Option<Handle> {
+        let mode = self.mode?;
+        let center = Point3::from_vec(instance.position);
+        let axes = self.axes(instance);
+        self.handles().iter()
+            .filter_map(|&handle| Some((handle_distance(mode, handle, ray, center, &axes, size)?, handle)))
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map(|(_, handle)| handle)
+    }
+
+    pub fn begin_drag(&mut self, ray: &Ray, index: usize, instance: &Instance, size: f32) -> bool {
+        let Some(handle) = self.hit(ray, instance, size) else { return false };
+        let center = Point3::from_vec(instance.position);
+        let axes = self.axes(instance);
+        let start = match (self.mode, handle) {
+            (Some(GizmoMode::Rotate), Handle::Axis(i)) | (_, Handle::Plane(i)) => ray_plane(ray, center, axes[i]).map(|(_, point)| point),
+            (_, Handle::Axis(i)) => closest_on_axis(ray, center, axes[i]).map(|(s, _)| center + axes[i] * s),
+        };
+        let Some(start) = start else { return false };
+        self.drag = Some(Drag {
+            handle,
+            instance: index,
+            before: instance.clone(),
+            axes,
+            start,
+        });
+        true
+    }
+
+    // The dragged instance and its new transform, snapped to the increments
+    // when `snap` is set
+    pub fn drag(&self, ray: &Ray, snap: bool) -> Option<(usize, Instance)> {
+        let drag = self.drag.as_ref()?;
+        let before = &drag.before;
+        let center = Point3::from_vec(before.position);
+        let axes = drag.axes;
+        let snapped = |value: f32, step: f32| if snap { snap_to(value, step) } else { value };
+        let mut after = before.clone();
+        match (self.mode?, drag.handle) {
+            (GizmoMode::Translate, Handle::Axis(i)) => {
+                let (s, _) = closest_on_axis(ray, center, axes[i])?;
+                let moved = s - (drag.start - center).dot(axes[i]);
+                after.position += axes[i] * snapped(moved, TRANSLATE_SNAP);
+            }
+            (GizmoMode::Translate, Handle::Plane(i)) => {
+                let (_, point) = ray_plane(ray, center, axes[i])?;
+                let moved = point - drag.start;
+                for axis in [axes[(i + 1) % 3], axes[(i + 2) % 3]] {
+                    after.position += axis * snapped(moved.dot(axis), TRANSLATE_SNAP);
+                }
+            }
+            (GizmoMode::Rotate, Handle::Axis(i)) | (GizmoMode::Rotate, Handle::Plane(i)) => {
+                let (_, point) = ray_plane(ray, center, axes[i])?;
+                let (from, to) = (drag.start - center, point - center);
+                let angle = Rad(from.cross(to).dot(axes[i]).atan2(from.dot(to)));
+                let angle = Deg::from(angle);
+                let angle = Deg(snapped(angle.0, ROTATE_SNAP.0));
+                after.rotation = (Quaternion::from_axis_angle(axes[i], angle) * before.rotation).normalize();
+            }
+            (GizmoMode::Scale, Handle::Axis(i)) => {
+                let (s, _) = closest_on_axis(ray, center, axes[i])?;
+                let grabbed = (drag.start - center).dot(axes[i]);
+                if grabbed.abs() < f32::EPSILON {
+                    return None;
+                }
+                after.scale[i] = snapped(before.scale[i] * s / grabbed, SCALE_SNAP).max(MIN_SCALE);
+            }
+            (GizmoMode::Scale, Handle::Plane(i)) => {
+                let (_, point) = ray_plane(ray, center, axes[i])?;
+                let ratio = point.distance(center) / drag.start.distance(center);
+                for j in [(i + 1) % 3, (i + 2) % 3] {
+                    after.scale[j] = snapped(before.scale[j] * ratio, SCALE_SNAP).max(MIN_SCALE);
+                }
+            }
+        }
+        Some((drag.instance, after))
+    }
+
+    // None when nothing was dragged or the drag left the instance as it was
+    pub fn end_drag(&mut self, instances: &[Instance]) -> Option<TransformEdit> {
+        let drag = self.drag.take()?;
+        let after = instances.get(drag.instance)?.clone();
+        let unchanged = after.position == drag.before.position && after.rotation == drag.before.rotation && after.scale == drag.before.scale;
+        (!unchanged).then_some(TransformEdit {
+            instance: drag.instance,
+            before: drag.before,
+            after,
+        })
+    }
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// How far along the ray it meets handle, if it does
+fn handle_distance(mode: GizmoMode, handle: Handle, ray: &Ray, center: Point3<f32>, axes: &[Vector3<f32>; 3], size: f32) -> Option<f32> {
+    match (mode, handle) {
+        (GizmoMode::Rotate, Handle::Axis(i)) | (GizmoMode::Rotate, Handle::Plane(i)) => {
+            let (t, point) = ray_plane(ray, center, axes[i])?;
+            ((point.distance(center) - size).abs() < PICK_RADIUS * size).then_some(t)
+        }
+        (_, Handle::Axis(i)) => {
+            let (s, t) = closest_on_axis(ray, center, axes[i])?;
+            let gap = ray.at(t).distance(center + axes[i] * s);
+            (t >= 0.0 && (0.0..=size * (1.0 + TIP_SIZE)).contains(&s) && gap < PICK_RADIUS * size).then_some(t)
+        }
+        (_, Handle::Plane(i)) => {
+            let (t, point) = ray_plane(ray, center, axes[i])?;
+            let offset = point - center;
+            let inside = |axis: Vector3<f32>| (PLANE_MIN * size..=PLANE_MAX * size).contains(&offset.dot(axis));
+            (inside(axes[(i + 1) % 3]) && inside(axes[(i + 2) % 3])).then_some(t)
+        }
+    }
+}
+
+fn snap_to(value: f32, step: f32) -> f32 {
+    (value / step).round() * step
+}
+
+// Distance along the ray and the point where it crosses the plane, if it
+// does in front of the origin
+fn ray_plane(ray: &Ray, point: Point3<f32>, normal: Vector3<f32>) -> Option<(f32, Point3<f32>)> {
+    let facing = ray.direction.dot(normal);
+    if facing.abs() < 1e-6 {
+        return None;
+    }
+    let t = (point - ray.origin).dot(normal) / facing;
+    (t >= 0.0).then(|| (t, ray.at(t)))
+}
+
+// Parameters of the closest points between the ray and the line through
+// origin along axis: (along the axis, along the ray). None when parallel.
+fn closest_on_axis(ray: &Ray, origin: Point3<f32>, axis: Vector3<f32>) -> Option<(f32, f32)> {
+    let w = ray.origin - origin;
+    let (a, b, c) = (ray.direction.dot(ray.direction), ray.direction.dot(axis), axis.dot(axis));
+    let (d, e) = (ray.direction.dot(w), axis.dot(w));
+    let denominator = a * c - b * b;
+    if denominator.abs() < 1e-6 {
+        return None;
+    }
+    Some(((a * e - b * d) / denominator, (b * e - c * d) / denominator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn looking_down(x: f32, z: f32) -> Ray {
+        Ray::new(Point3::new(x, 10.0, z), -Vector3::unit_y())
+    }
+
+    #[test]
+    fn rays_meet_axes_and_planes() {
+        let ray = looking_down(2.0, 0.0);
+        let (s, t) = closest_on_axis(&ray, Point3::origin(), Vector3::unit_x()).unwrap();
+        assert!((s - 2.0).abs() < 1e-5 && (t - 10.0).abs() < 1e-5);
+        let (t, point) = ray_plane(&ray, Point3::origin(), Vector3::unit_y()).unwrap();
+        assert!((t - 10.0).abs() < 1e-5 && point.distance(Point3::new(2.0, 0.0, 0.0)) < 1e-5);
+        assert!(ray_plane(&ray, Point3::new(0.0, 20.0, 0.0), Vector3::unit_y()).is_none());
+        assert_eq!(snap_to(0.37, 0.25), 0.25);
+        assert_eq!(snap_to(-0.4, 0.25), -0.5);
+    }
+
+    #[test]
+    fn dragging_handles_moves_and_turns_the_instance() {
+        let instance = Instance::new(Vector3::zero());
+        let mut gizmo = Gizmo::new();
+        gizmo.mode = Some(GizmoMode::Translate);
+
+        // grab the x arrow from above and pull it along
+        assert_eq!(gizmo.hit(&looking_down(0.5, 0.0), &instance, 1.0), Some(Handle::Axis(0)));
+        assert!(gizmo.begin_drag(&looking_down(0.5, 0.0), 3, &instance, 1.0));
+        let (index, moved) = gizmo.drag(&looking_down(1.8, 0.3), false).unwrap();
+        assert_eq!(index, 3);
+        assert!((moved.position - Vector3::new(1.3, 0.0, 0.0)).magnitude() < 1e-5);
+        let (_, snapped) = gizmo.drag(&looking_down(1.8, 0.3), true).unwrap();
+        assert!((snapped.position - Vector3::new(1.25, 0.0, 0.0)).magnitude() < 1e-5);
+        let edit = gizmo.end_drag(&[moved.clone(), moved.clone(), moved.clone(), moved]).unwrap();
+        assert!(edit.before.position.is_zero());
+        assert!(!gizmo.is_dragging());
+
+        // a quarter turn around y, grabbing the ring on +x and letting go on -z
+        gizmo.mode = Some(GizmoMode::Rotate);
+        assert!(gizmo.begin_drag(&looking_down(1.0, 0.0), 0, &instance, 1.0));
+        let (_, turned) = gizmo.drag(&looking_down(0.0, -1.0), true).unwrap();
+        let x = turned.rotation.rotate_vector(Vector3::unit_x());
+        assert!((x - Vector3::new(0.0, 0.0, -1.0)).magnitude() < 1e-5);
+        assert!(gizmo.end_drag(&[instance]).is_none());
+    }
+}
@@ -0,0 +1,68 @@
+struct CullUniform {
+    // xyz is the normal, w the distance, inside is positive
+    planes: array<vec4<f32>, 6>,
+    num_instances: u32,
+    num_meshes: u32,
+}
+@group(0) @binding(0)
+var<uniform> cull: CullUniform;
+
+// InstanceRaw is a mat4x4 followed by a tightly packed mat3x3, which doesn't
+// match WGSL's storage layout, so instances are read and copied as raw floats
+const INSTANCE_FLOATS: u32 = 29u;
+
+@group(0) @binding(1)
+var<storage, read> instances: array<f32>;
+
+// model space bounding sphere per mesh, xyz center and w radius
+@group(0) @binding(2)
+var<storage, read> spheres: array<vec4<f32>>;
+
+// one region of num_instances slots per mesh
+@group(0) @binding(3)
+var<storage, read_write> culled_instances: array<f32>;
+
+// matches wgpu::util::DrawIndexedIndirect
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance = id.x;
+    let mesh = id.y;
+    if instance >= cull.num_instances || mesh >= cull.num_meshes {
+        return;
+    }
+
+    let base = instance * INSTANCE_FLOATS;
+    let matrix = mat4x4<f32>(
+        vec4<f32>(instances[base + 0u], instances[base + 1u], instances[base + 2u], instances[base + 3u]),
+        vec4<f32>(instances[base + 4u], instances[base + 5u], instances[base + 6u], instances[base + 7u]),
+        vec4<f32>(instances[base + 8u], instances[base + 9u], instances[base + 10u], instances[base + 11u]),
+        vec4<f32>(instances[base + 12u], instances[base + 13u], instances[base + 14u], instances[base + 15u]),
+    );
+
+    let sphere = spheres[mesh];
+    let center = (matrix * vec4<f32>(sphere.xyz, 1.0)).xyz;
+    let scale = max(length(matrix[0].xyz), max(length(matrix[1].xyz), length(matrix[2].xyz)));
+    let radius = sphere.w * scale;
+    for (var i = 0u; i < 6u; i++) {
+        let plane = cull.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+
+    let slot = atomicAdd(&draws[mesh].instance_count, 1u);
+    let dst = (mesh * cull.num_instances + slot) * INSTANCE_FLOATS;
+    for (var i = 0u; i < INSTANCE_FLOATS; i++) {
+        culled_instances[dst + i] = instances[base + i];
+    }
+}
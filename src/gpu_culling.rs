@@ -0,0 +1,241 @@
+use wgpu::util::DeviceExt;
+
+use crate::culling::Frustum;
+use crate::instancing::InstanceRaw;
+use crate::model::Model;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    num_instances: u32,
+    num_meshes: u32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [u32; 2],
+}
+
+// Culls every (instance, mesh) pair against the frustum in a compute pass and
+// writes one DrawIndexedIndirect per mesh, so no visibility data has to come
+// back to the CPU. Mesh i reads its instances from region i of
+// culled_instance_buffer, see DrawModel::draw_model_indirect.
+pub struct GpuCuller {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sphere_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    pub culled_instance_buffer: wgpu::Buffer,
+    pub indirect_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pub num_instances: u32,
+    // instances the output buffers have room for
+    capacity: u32,
+    num_meshes: u32,
+}
+
+impl GpuCuller {
+    pub fn new(
+        device: &wgpu::Device,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        num_instances: u32,
+    ) -> Self {
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GPU Cull Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, false),
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU Cull Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_cull.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("GPU Cull Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Cull Uniform Buffer"),
+            size: std::mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let spheres = model.meshes.iter()
+            .map(|mesh| {
+                let sphere = mesh.bounding_sphere;
+                [sphere.center.x, sphere.center.y, sphere.center.z, sphere.radius]
+            })
+            .collect::<Vec<_>>();
+        let sphere_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Cull Sphere Buffer"),
+            contents: bytemuck::cast_slice(&spheres),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let num_meshes = model.meshes.len() as u32;
+        let capacity = num_instances.max(1).next_power_of_two();
+        let (culled_instance_buffer, indirect_buffer) = Self::create_output_buffers(device, capacity, num_meshes);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &uniform_buffer,
+            instance_buffer,
+            &sphere_buffer,
+            &culled_instance_buffer,
+            &indirect_buffer,
+        );
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sphere_buffer,
+            uniform_buffer,
+            culled_instance_buffer,
+            indirect_buffer,
+            bind_group,
+            num_instances,
+            capacity,
+            num_meshes,
+        }
+    }
+
+    fn create_output_buffers(device: &wgpu::Device, capacity: u32, num_meshes: u32) -> (wgpu::Buffer, wgpu::Buffer) {
+        let culled_instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Culled Instance Buffer"),
+            size: (capacity * num_meshes.max(1)) as wgpu::BufferAddress
+                * std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: false,
+        });
+        let indirect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Cull Indirect Buffer"),
+            size: num_meshes.max(1) as wgpu::BufferAddress
+                * std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (culled_instance_buffer, indirect_buffer)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        instance_buffer: &wgpu::Buffer,
+        sphere_buffer: &wgpu::Buffer,
+        culled_instance_buffer: &wgpu::Buffer,
+        indirect_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GPU Cull Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: uniform_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: instance_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: sphere_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: culled_instance_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: indirect_buffer.as_entire_binding() },
+            ],
+        })
+    }
+
+    // Has to be called whenever the instance count changes, with recreated
+    // set when the instance buffer itself was recreated. The output buffers
+    // only grow, and the bind group is only rebuilt when a buffer changed.
+    pub fn set_instance_buffer(&mut self, device: &wgpu::Device, instance_buffer: &wgpu::Buffer, num_instances: u32, recreated: bool) {
+        self.num_instances = num_instances;
+        let grown = num_instances > self.capacity;
+        if grown {
+            self.capacity = num_instances.next_power_of_two();
+            (self.culled_instance_buffer, self.indirect_buffer) =
+                Self::create_output_buffers(device, self.capacity, self.num_meshes);
+        }
+        if !grown && !recreated {
+            return;
+        }
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.uniform_buffer,
+            instance_buffer,
+            &self.sphere_buffer,
+            &self.culled_instance_buffer,
+            &self.indirect_buffer,
+        );
+    }
+
+    // Resets the draw arguments and records the cull dispatch. The render pass
+    // that consumes indirect_buffer has to be recorded after this.
+    pub fn cull(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        frustum: &Frustum,
+        model: &Model,
+    ) {
+        let uniform = CullUniform {
+            planes: frustum.planes.map(|plane| [plane.normal.x, plane.normal.y, plane.normal.z, plane.distance]),
+            num_instances: self.num_instances,
+            num_meshes: self.num_meshes,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let draws = model.meshes.iter()
+            .flat_map(|mesh| {
+                wgpu::util::DrawIndexedIndirect {
+                    vertex_count: mesh.num_elements,
+                    instance_count: 0,
+                    base_index: 0,
+                    vertex_offset: 0,
+                    base_instance: 0,
+                }.as_bytes().to_vec()
+            })
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.indirect_buffer, 0, &draws);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GPU Cull Pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(
+            self.num_instances.div_ceil(WORKGROUP_SIZE),
+            self.num_meshes,
+            1,
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use instant::{Duration, Instant};
+
+use crate::instancing::Instance;
+use crate::model::TextureSlot;
+use crate::state::Light;
+use crate::texture::Texture;
+
+// The oldest edits are forgotten past this
+const MAX_EDITS: usize = 100;
+// Continuous edits closer together than this undo as one
+pub const COALESCE_WINDOW: Duration = Duration::from_millis(500);
+
+// An edit to the scene, with what it takes to revert and reapply it
+pub enum Edit {
+    Transform { instance: usize, before: Instance, after: Instance },
+    AddInstance { index: usize, instance: Instance },
+    RemoveInstance { index: usize, instance: Instance },
+    Light { before: Light, after: Light },
+    // swapping is its own inverse, so this holds whichever texture isn't showing
+    MaterialTexture { material: usize, slot: TextureSlot, texture: Texture },
+}
+
+impl Edit {
+    pub fn label(&self) -> String {
+        match self {
+            Edit::Transform { instance, .. } => format!("Transform instance {}", instance),
+            Edit::AddInstance { index, .. } => format!("Add instance {}", index),
+            Edit::RemoveInstance { index, .. } => format!("Remove instance {}", index),
+            Edit::Light { .. } => "Edit light".to_string(),
+            Edit::MaterialTexture { material, slot, .. } => format!("Swap {:?} texture of material {}", slot, material),
+        }
+    }
+
+    // Folds next into this edit if it carries on from it
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (Edit::Transform { instance, after, .. }, Edit::Transform { instance: next_instance, after: next_after, .. }) if instance == next_instance => {
+                after.clone_from(next_after);
+                true
+            }
+            (Edit::Light { after, .. }, Edit::Light { after: next_after, .. }) => {
+                *after = *next_after;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+struct Entry {
+    edit: Edit,
+    // continuous edits up to then are merged into this one
+    open_until: Option<Instant>,
+}
+
+// Edits that have been made, newest last, and the ones undone since, next
+// to redo last. The history only keeps track, whoever owns the scene
+// applies what undo and redo hand out and gives it back.
+#[derive(Default)]
+pub struct History {
+    done: Vec<Entry>,
+    undone: Vec<Edit>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // An edit that's already been made to the scene
+    pub fn record(&mut self, edit: Edit) {
+        self.push(edit, None);
+    }
+
+    // A step of a drag or a held key, merged into the last edit while that's
+    // the same kind and the steps keep coming
+    pub fn record_continuous(&mut self, edit: Edit, now: Instant) {
+        let open_until = Some(now + COALESCE_WINDOW);
+        if let Some(last) = self.done.last_mut() {
+            if last.open_until.is_some_and(|until| now <= until) && last.edit.merge(&edit) {
+                last.open_until = open_until;
+                self.undone.clear();
+                return;
+            }
+        }
+        self.push(edit, open_until);
+    }
+
+    fn push(&mut self, edit: Edit, open_until: Option<Instant>) {
+        self.undone.clear();
+        self.done.push(Entry { edit, open_until });
+        if self.done.len() > MAX_EDITS {
+            self.done.remove(0);
+        }
+    }
+
+    // The newest edit, to be reverted and handed back with undone()
+    pub fn undo(&mut self) -> Option<Edit> {
+        self.done.pop().map(|entry| entry.edit)
+    }
+
+    pub fn undone(&mut self, edit: Edit) {
+        self.undone.push(edit);
+    }
+
+    // The last undone edit, to be reapplied and handed back with redone()
+    pub fn redo(&mut self) -> Option<Edit> {
+        self.undone.pop()
+    }
+
+    // Redone edits are closed, whatever comes next is a new edit
+    pub fn redone(&mut self, edit: Edit) {
+        self.done.push(Entry { edit, open_until: None });
+    }
+
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    // Fits in the window title: how many edits are applied out of all that
+    // are kept, and what undo and redo would do next
+    pub fn summary(&self) -> String {
+        let total = self.done.len() + self.undone.len();
+        let undo = self.done.last().map_or("nothing to undo".to_string(), |entry| format!("undo {}", entry.edit.label()));
+        let redo = self.undone.last().map_or(String::new(), |edit| format!(", redo {}", edit.label()));
+        format!("history {}/{}: {}{}", self.done.len(), total, undo, redo)
+    }
+
+    // One line per edit, oldest first, with the newest one that's still
+    // applied marked
+    pub fn panel(&self) -> Vec<String> {
+        let done = self.done.iter().enumerate().map(|(i, entry)| {
+            let marker = if i + 1 == self.done.len() { ">" } else { " " };
+            format!("{} {:>3} {}", marker, i + 1, entry.edit.label())
+        });
+        let undone = self.undone.iter().rev().enumerate().map(|(i, edit)| {
+            format!("  {:>3} {} (undone)", self.done.len() + i + 1, edit.label())
+        });
+        done.chain(undone).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector3;
+
+    fn moved_to(x: f32) -> Edit {
+        Edit::Transform {
+            instance: 0,
+            before: Instance::new(Vector3::new(x - 1.0, 0.0, 0.0)),
+            after: Instance::new(Vector3::new(x, 0.0, 0.0)),
+        }
+    }
+
+    fn position(edit: &Edit) -> (f32, f32) {
+        match edit {
+            Edit::Transform { before, after, .. } => (before.position.x, after.position.x),
+            _ => panic!("not a transform"),
+        }
+    }
+
+    #[test]
+    fn continuous_edits_coalesce_until_they_pause() {
+        let mut history = History::new();
+        let start = Instant::now();
+        for step in 1..=3 {
+            history.record_continuous(moved_to(step as f32), start + Duration::from_millis(100 * step));
+        }
+        // a pause starts a new edit, and so does a different kind of edit
+        history.record_continuous(moved_to(4.0), start + Duration::from_secs(2));
+        history.record_continuous(Edit::AddInstance { index: 1, instance: Instance::new(Vector3::new(0.0, 0.0, 0.0)) }, start + Duration::from_secs(2));
+        assert_eq!(history.panel().len(), 3);
+
+        assert!(matches!(history.undo(), Some(Edit::AddInstance { index: 1, .. })));
+        assert_eq!(history.undo().map(|edit| position(&edit)), Some((3.0, 4.0)));
+        let drag = history.undo().unwrap();
+        assert_eq!(position(&drag), (0.0, 3.0));
+        assert!(!history.can_undo());
+
+        // redoing and then editing drops what's left to redo
+        history.undone(drag);
+        let drag = history.redo().unwrap();
+        history.redone(drag);
+        assert_eq!(history.panel(), [">   1 Transform instance 0"]);
+        let drag = history.undo().unwrap();
+        history.undone(drag);
+        assert!(history.can_redo());
+        assert_eq!(history.summary(), "history 0/1: nothing to undo, redo Transform instance 0");
+        history.record(moved_to(9.0));
+        assert!(!history.can_redo());
+    }
+}
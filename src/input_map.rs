@@ -0,0 +1,476 @@
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::{anyhow, bail, Context};
+use winit::event::{ModifiersState, MouseButton, VirtualKeyCode};
+
+// Loaded over the defaults at startup when it exists
+pub const INPUT_MAP_FILE: &str = "input_map.txt";
+
+// Everything the viewer does on a key or mouse button. Held actions like
+// camera.forward last until the key is released, the rest fire on press.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    CameraForward,
+    CameraBackward,
+    CameraLeft,
+    CameraRight,
+    CameraUp,
+    CameraDown,
+    CameraLook,
+    CameraPan,
+    CameraToggleMode,
+    CameraToggleProjection,
+    CameraFrameScene,
+    ViewFront,
+    ViewBack,
+    ViewLeft,
+    ViewRight,
+    ViewTop,
+    ViewBottom,
+    ModelRotateUp,
+    ModelRotateDown,
+    ModelRotateLeft,
+    ModelRotateRight,
+    Select,
+    SelectNext,
+    SelectionOverlay,
+    AddInstance,
+    RemoveInstance,
+    StressTest,
+    GizmoTranslate,
+    GizmoRotate,
+    GizmoScale,
+    GizmoSpace,
+    Undo,
+    Redo,
+    History,
+    DebugShaded,
+    DebugWireframe,
+    DebugNormals,
+    DebugTangents,
+    DebugUvChecker,
+    DebugMaterialId,
+    DebugHelpers,
+    DepthMode,
+    PresentMode,
+    FrameCap,
+    CullMode,
+    LayoutNext,
+    LayoutMaximize,
+    SimPause,
+    SimStep,
+    SimSlower,
+    SimFaster,
+    Screenshot,
+    Record,
+    PathAddKey,
+    PathClear,
+    PathPlay,
+    PathLooping,
+    PathInterpolation,
+    PathSave,
+    PathLoad,
+    Quit,
+    NewWindow,
+    ReloadBindings,
+}
+
+// In declaration order, with the name used in input map files and the
+// default bindings
+const ACTIONS: &[(Action, &str, &str)] = &[
+    (Action::CameraForward, "camera.forward", "W"),
+    (Action::CameraBackward, "camera.backward", "S"),
+    (Action::CameraLeft, "camera.left", "A"),
+    (Action::CameraRight, "camera.right", "D"),
+    (Action::CameraUp, "camera.up", "Space"),
+    (Action::CameraDown, "camera.down", "LControl"),
+    (Action::CameraLook, "camera.look", "mouse:Left"),
+    (Action::CameraPan, "camera.pan", "mouse:Middle"),
+    (Action::CameraToggleMode, "camera.toggle_mode", "O"),
+    (Action::CameraToggleProjection, "camera.toggle_projection", "Numpad5"),
+    (Action::CameraFrameScene, "camera.frame_scene", "F"),
+    (Action::ViewFront, "view.front", "Numpad1"),
+    (Action::ViewBack, "view.back", "ctrl+Numpad1"),
+    (Action::ViewLeft, "view.left", "ctrl+Numpad3"),
+    (Action::ViewRight, "view.right", "Numpad3"),
+    (Action::ViewTop, "view.top", "Numpad7"),
+    (Action::ViewBottom, "view.bottom", "ctrl+Numpad7"),
+    (Action::ModelRotateUp, "model.rotate_up", "Up"),
+    (Action::ModelRotateDown, "model.rotate_down", "Down"),
+    (Action::ModelRotateLeft, "model.rotate_left", "Left"),
+    (Action::ModelRotateRight, "model.rotate_right", "Right"),
+    (Action::Select, "scene.select", "mouse:Left"),
+    (Action::SelectNext, "scene.select_next", "Tab"),
+    (Action::SelectionOverlay, "scene.selection_overlay", "X"),
+    (Action::AddInstance, "scene.add_instance", "Equals"),
+    (Action::RemoveInstance, "scene.remove_instance", "Minus"),
+    (Action::StressTest, "scene.stress_test", "T"),
+    (Action::GizmoTranslate, "gizmo.translate", "Key1"),
+    (Action::GizmoRotate, "gizmo.rotate", "Key2"),
+    (Action::GizmoScale, "gizmo.scale", "Key3"),
+    (Action::GizmoSpace, "gizmo.toggle_space", "J"),
+    (Action::Undo, "edit.undo", "ctrl+Z"),
+    (Action::Redo, "edit.redo", "ctrl+Y ctrl+shift+Z"),
+    (Action::History, "edit.history", "H"),
+    (Action::DebugShaded, "debug.shaded", "F1"),
+    (Action::DebugWireframe, "debug.wireframe", "F2"),
+    (Action::DebugNormals, "debug.normals", "F3"),
+    (Action::DebugTangents, "debug.tangents", "F4"),
+    (Action::DebugUvChecker, "debug.uv_checker", "F5"),
+    (Action::DebugMaterialId, "debug.material_id", "F6"),
+    (Action::DebugHelpers, "debug.helpers", "G"),
+    (Action::DepthMode, "render.depth_mode", "Z"),
+    (Action::PresentMode, "render.present_mode", "V"),
+    (Action::FrameCap, "render.frame_cap", "N"),
+    (Action::CullMode, "render.cull_mode", "C"),
+    (Action::LayoutNext, "layout.next", "Q"),
+    (Action::LayoutMaximize, "layout.maximize", "M"),
+    (Action::SimPause, "sim.pause", "Pause"),
+    (Action::SimStep, "sim.step", "Period"),
+    (Action::SimSlower, "sim.slower", "LBracket"),
+    (Action::SimFaster, "sim.faster", "RBracket"),
+    (Action::Screenshot, "capture.screenshot", "F12"),
+    (Action::Record, "capture.record", "F8"),
+    (Action::PathAddKey, "path.add_key", "K"),
+    (Action::PathClear, "path.clear", "Back"),
+    (Action::PathPlay, "path.play", "P"),
+    (Action::PathLooping, "path.looping", "L"),
+    (Action::PathInterpolation, "path.interpolation", "I"),
+    (Action::PathSave, "path.save", "F9"),
+    (Action::PathLoad, "path.load", "F10"),
+    (Action::Quit, "app.quit", "Escape"),
+    (Action::NewWindow, "app.new_window", "F7"),
+    (Action::ReloadBindings, "app.reload_bindings", "ctrl+R"),
+];
+
+// Actions that may share a binding: a click selects and a drag moves the camera
+const SHARED: &[[Action; 2]] = &[[Action::CameraLook, Action::Select]];
+// Actions that may sit on a modifier key other actions use in chords:
+// camera.down was on LControl before there were any ctrl chords
+const HELD_FOR_CHORDS: &[Action] = &[Action::CameraDown];
+
+impl Action {
+    pub fn all() -> impl Iterator<Item = Action> {
+        ACTIONS.iter().map(|(action, _, _)| *action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTIONS[self as usize].1
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::all().find(|action| action.name() == name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+// An input and the modifiers held with it, written like ctrl+shift+Z or
+// mouse:Middle
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub input: Input,
+    pub modifiers: ModifiersState,
+}
+
+const MODIFIERS: [(ModifiersState, &str); 4] = [
+    (ModifiersState::CTRL, "ctrl"),
+    (ModifiersState::SHIFT, "shift"),
+    (ModifiersState::ALT, "alt"),
+    (ModifiersState::LOGO, "logo"),
+];
+
+impl Binding {
+    pub fn key(key: VirtualKeyCode) -> Self {
+        Self { input: Input::Key(key), modifiers: ModifiersState::empty() }
+    }
+
+    // The modifier that's held while this binding's key is, for LControl
+    // and the like
+    fn held_modifier(&self) -> Option<ModifiersState> {
+        match self.input {
+            Input::Key(VirtualKeyCode::LControl | VirtualKeyCode::RControl) => Some(ModifiersState::CTRL),
+            Input::Key(VirtualKeyCode::LShift | VirtualKeyCode::RShift) => Some(ModifiersState::SHIFT),
+            Input::Key(VirtualKeyCode::LAlt | VirtualKeyCode::RAlt) => Some(ModifiersState::ALT),
+            Input::Key(VirtualKeyCode::LWin | VirtualKeyCode::RWin) => Some(ModifiersState::LOGO),
+            _ => None,
+        }
+    }
+
+    // Holding this binding down is part of pressing chord
+    fn is_held_for(&self, chord: &Binding) -> bool {
+        self.held_modifier().is_some_and(|modifier| chord.modifiers.contains(modifier))
+    }
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (modifier, name) in MODIFIERS {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+        match self.input {
+            Input::Key(key) => write!(f, "{:?}", key),
+            Input::Mouse(MouseButton::Other(button)) => write!(f, "mouse:{}", button),
+            Input::Mouse(button) => write!(f, "mouse:{:?}", button),
+        }
+    }
+}
+
+impl FromStr for Binding {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let mut parts = text.split('+').collect::<Vec<_>>();
+        let input = parts.pop().unwrap_or_default();
+        let mut modifiers = ModifiersState::empty();
+        for part in parts {
+            let (modifier, _) = MODIFIERS.iter()
+                .find(|(_, name)| name.eq_ignore_ascii_case(part))
+                .ok_or_else(|| anyhow!("unknown modifier {}", part))?;
+            modifiers |= *modifier;
+        }
+        let input = match input.strip_prefix("mouse:") {
+            Some(button) => Input::Mouse(match button.to_ascii_lowercase().as_str() {
+                "left" => MouseButton::Left,
+                "right" => MouseButton::Right,
+                "middle" => MouseButton::Middle,
+                other => MouseButton::Other(other.parse().with_context(|| format!("unknown mouse button {}", button))?),
+            }),
+            None => Input::Key(KEYS.iter()
+                .copied()
+                .find(|key| format!("{:?}", key).eq_ignore_ascii_case(input))
+                .ok_or_else(|| anyhow!("unknown key {}", input))?),
+        };
+        Ok(Self { input, modifiers })
+    }
+}
+
+// Two actions that would fire together: both on the same binding, or one
+// on a modifier key that's held down for the other's chord
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Conflict {
+    // one per action
+    pub bindings: [Binding; 2],
+    pub actions: [Action; 2],
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [a, b] = self.actions.map(Action::name);
+        if self.bindings[0] == self.bindings[1] {
+            write!(f, "{} is bound to both {} and {}", self.bindings[0], a, b)
+        } else {
+            write!(f, "{} on {} is held down for {} on {}", a, self.bindings[0], b, self.bindings[1])
+        }
+    }
+}
+
+// Which bindings trigger each action, indexed by the action
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputMap {
+    bindings: Vec<Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let bindings = ACTIONS.iter()
+            .map(|(_, _, defaults)| defaults.split_whitespace().map(|binding| binding.parse().unwrap()).collect())
+            .collect();
+        Self { bindings }
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        &self.bindings[action as usize]
+    }
+
+    // Replaces every binding of action, an empty list unbinds it
+    pub fn rebind(&mut self, action: Action, bindings: Vec<Binding>) {
+        self.bindings[action as usize] = bindings;
+    }
+
+    // Actions triggered by pressing input with modifiers held. Bindings with
+    // exactly those modifiers win, otherwise ones without any still apply,
+    // so W keeps moving the camera with shift held.
+    pub fn pressed(&self, input: Input, modifiers: ModifiersState) -> Vec<Action> {
+        let bound = |modifiers: ModifiersState| {
+            Action::all()
+                .filter(|&action| self.bindings(action).contains(&Binding { input, modifiers }))
+                .collect::<Vec<_>>()
+        };
+        let exact = bound(modifiers & (ModifiersState::CTRL | ModifiersState::SHIFT | ModifiersState::ALT | ModifiersState::LOGO));
+        if exact.is_empty() { bound(ModifiersState::empty()) } else { exact }
+    }
+
+    // Actions to end when input is released, whatever the modifiers are by then
+    pub fn released(&self, input: Input) -> Vec<Action> {
+        Action::all()
+            .filter(|&action| self.bindings(action).iter().any(|binding| binding.input == input))
+            .collect()
+    }
+
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        let actions = Action::all().collect::<Vec<_>>();
+        for (i, &a) in actions.iter().enumerate() {
+            for &b in &actions[i + 1..] {
+                if SHARED.contains(&[a, b]) || SHARED.contains(&[b, a]) {
+                    continue;
+                }
+                for &binding_a in self.bindings(a) {
+                    for &binding_b in self.bindings(b) {
+                        let held = |action: Action, binding: Binding, chord: &Binding| {
+                            !HELD_FOR_CHORDS.contains(&action) && binding.is_held_for(chord)
+                        };
+                        if binding_a == binding_b || held(a, binding_a, &binding_b) {
+                            conflicts.push(Conflict { bindings: [binding_a, binding_b], actions: [a, b] });
+                        } else if held(b, binding_b, &binding_a) {
+                            conflicts.push(Conflict { bindings: [binding_b, binding_a], actions: [b, a] });
+                        }
+                    }
+                }
+            }
+        }
+        conflicts
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading input map {}", path.display()))?;
+        text.parse()
+    }
+}
+
+// Plain text, one action per line followed by its bindings, # starts a
+// comment:
+//   camera.forward W Up
+//   edit.redo ctrl+Y ctrl+shift+Z
+//   camera.pan mouse:Right
+//   camera.up none
+// Actions that aren't listed keep their default bindings.
+impl std::fmt::Display for InputMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for action in Action::all() {
+            write!(f, "{:<26}", action.name())?;
+            let bindings = self.bindings(action);
+            if bindings.is_empty() {
+                write!(f, " none")?;
+            }
+            for binding in bindings {
+                write!(f, " {}", binding)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for InputMap {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let mut map = Self::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(name) = words.next() else {
+                continue;
+            };
+            let context = || format!("line {}: {}", number + 1, line);
+            let Some(action) = Action::from_name(name) else {
+                bail!("unknown action {} on {}", name, context());
+            };
+            let bindings = match words.collect::<Vec<_>>().as_slice() {
+                [] => bail!("no bindings on {}, use none to unbind", context()),
+                ["none"] => Vec::new(),
+                words => words.iter().map(|word| word.parse()).collect::<anyhow::Result<_>>().with_context(context)?,
+            };
+            map.rebind(action, bindings);
+        }
+        Ok(map)
+    }
+}
+
+// Every key winit knows, to look them up by name
+const KEYS: [VirtualKeyCode; 163] = {
+    use VirtualKeyCode::*;
+    [
+        Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+        Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+        F13, F14, F15, F16, F17, F18, F19, F20, F21, F22, F23, F24,
+        Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+        Left, Up, Right, Down, Back, Return, Space, Compose, Caret, Numlock,
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+        NumpadAdd, NumpadDivide, NumpadDecimal, NumpadComma, NumpadEnter, NumpadEquals,
+        NumpadMultiply, NumpadSubtract,
+        AbntC1, AbntC2, Apostrophe, Apps, Asterisk, At, Ax, Backslash, Calculator, Capital,
+        Colon, Comma, Convert, Equals, Grave, Kana, Kanji, LAlt, LBracket, LControl, LShift,
+        LWin, Mail, MediaSelect, MediaStop, Minus, Mute, MyComputer, NavigateForward,
+        NavigateBackward, NextTrack, NoConvert, OEM102, Period, PlayPause, Plus, Power,
+        PrevTrack, RAlt, RBracket, RControl, RShift, RWin, Semicolon, Slash, Sleep, Stop,
+        Sysrq, Tab, Underline, Unlabeled, VolumeDown, VolumeUp, Wake, WebBack, WebFavorites,
+        WebForward, WebHome, WebRefresh, WebSearch, WebStop, Yen, Copy, Paste, Cut,
+    ]
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_consistent() {
+        for (i, (action, _, _)) in ACTIONS.iter().enumerate() {
+            assert_eq!(*action as usize, i, "{:?} is out of order", action);
+        }
+        let map = InputMap::default();
+        assert_eq!(map.conflicts(), []);
+        assert_eq!(map.to_string().parse::<InputMap>().unwrap(), map);
+    }
+
+    #[test]
+    fn files_rebind_over_the_defaults() {
+        let map: InputMap = "
+            # azerty
+            camera.forward Z   # where W is on qwerty
+            camera.up none
+            camera.pan alt+mouse:Left mouse:4
+            sim.pause LShift
+        ".parse().unwrap();
+        assert_eq!(map.bindings(Action::CameraBackward), [Binding::key(VirtualKeyCode::S)]);
+        assert_eq!(map.bindings(Action::CameraUp), []);
+        assert_eq!(map.to_string().parse::<InputMap>().unwrap(), map);
+
+        // ctrl+Z is still undo, and Z now clashes with the depth mode
+        let z = Input::Key(VirtualKeyCode::Z);
+        assert_eq!(map.pressed(z, ModifiersState::CTRL), [Action::Undo]);
+        assert_eq!(map.pressed(z, ModifiersState::SHIFT), [Action::CameraForward, Action::DepthMode]);
+        assert_eq!(map.released(z), [Action::CameraForward, Action::Undo, Action::Redo, Action::DepthMode]);
+        let conflicts = map.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].to_string(), "Z is bound to both camera.forward and render.depth_mode");
+        // holding shift for ctrl+shift+Z would also pause
+        assert_eq!(conflicts[1].to_string(), "sim.pause on LShift is held down for edit.redo on ctrl+shift+Z");
+        // camera.down is allowed on a modifier, but not on a chord's own binding
+        assert_eq!("camera.down LShift".parse::<InputMap>().unwrap().conflicts(), []);
+        assert_eq!("camera.down ctrl+R".parse::<InputMap>().unwrap().conflicts().len(), 1);
+        assert_eq!(map.pressed(Input::Mouse(MouseButton::Other(4)), ModifiersState::empty()), [Action::CameraPan]);
+
+        assert!("camera.sideways W".parse::<InputMap>().is_err());
+        assert!("camera.forward hyper+W".parse::<InputMap>().is_err());
+        assert!("camera.forward".parse::<InputMap>().is_err());
+    }
+}
@@ -1,11 +1,15 @@
-use cgmath::Matrix4;
+use std::ops::Range;
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4};
 
-pub const NUM_INSTANCES_PER_ROW: u32 = 1;
-//pub const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
+pub const STRESS_TEST_INSTANCES_PER_ROW: u32 = 100;
 
+#[derive(Clone, Debug)]
 pub struct Instance {
     pub position: cgmath::Vector3<f32>,
     pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+    pub tint: [f32; 4],
 }
 
 #[repr(C)]
@@ -13,16 +17,44 @@ pub struct Instance {
 pub struct InstanceRaw {
     pub model: [[f32; 4]; 4],
     pub normal: [[f32; 3]; 3],
+    pub tint: [f32; 4],
 }
 
 impl Instance {
+    pub fn new(position: cgmath::Vector3<f32>) -> Self {
+        Self {
+            position,
+            rotation: cgmath::Quaternion::one(),
+            scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            tint: [1.0; 4],
+        }
+    }
+
+    // For rendering between two simulation steps
+    pub fn lerp(&self, other: &Instance, t: f32) -> Instance {
+        Instance {
+            position: self.position.lerp(other.position, t),
+            rotation: self.rotation.nlerp(other.rotation, t),
+            scale: self.scale.lerp(other.scale, t),
+            tint: std::array::from_fn(|i| self.tint[i] + (other.tint[i] - self.tint[i]) * t),
+        }
+    }
+
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
+        let model = self.model_matrix();
+        // inverse-transpose so normals stay perpendicular under non-uniform scale
+        let linear = Matrix3::from_cols(model.x.truncate(), model.y.truncate(), model.z.truncate());
+        let normal = linear.invert().unwrap_or(Matrix3::identity()).transpose();
         InstanceRaw {
-            model: (Matrix4::from_translation(self.position)
-                * Matrix4::from(self.rotation)
-                * Matrix4::from_scale(0.5))
-            .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
+            model: model.into(),
+            normal: normal.into(),
+            tint: self.tint,
         }
     }
 }
@@ -72,7 +104,415 @@ impl InstanceRaw {
                     shader_location: 11,
                     format: wgpu::VertexFormat::Float32x3,
                 },
+                // Tint
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 25]>() as wgpu::BufferAddress,
+                    shader_location: 12,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
 }
+
+// Square grid of tinted cubes centered on the origin, for stress testing
+pub fn stress_test_instances(per_row: u32, spacing: f32) -> Vec<Instance> {
+    let half = per_row as f32 / 2.0;
+    (0..per_row).flat_map(|z| {
+        (0..per_row).map(move |x| {
+            let mut instance = Instance::new(cgmath::Vector3::new(
+                spacing * (x as f32 - half),
+                0.0,
+                spacing * (z as f32 - half),
+            ));
+            instance.scale = cgmath::Vector3::new(0.5, 0.5, 0.5);
+            instance.tint = [x as f32 / per_row as f32, 0.5, z as f32 / per_row as f32, 1.0];
+            instance
+        })
+    }).collect()
+}
+
+// The instances as edited on the CPU and what has to go to the GPU for them.
+// Edits only mark the touched range dirty, so just that range is uploaded.
+//
+// instances is the latest simulation state and previous the one before it,
+// raw holds what's rendered, interpolated between the two by interpolate().
+pub struct InstanceList {
+    instances: Vec<Instance>,
+    previous: Vec<Instance>,
+    raw: Vec<InstanceRaw>,
+    dirty: Option<Range<usize>>,
+    // edited since begin_step, so previous and instances differ
+    moving: Option<Range<usize>>,
+}
+
+impl InstanceList {
+    pub fn new(instances: Vec<Instance>) -> Self {
+        let raw = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let dirty = Some(0..raw.len());
+        Self {
+            previous: instances.clone(),
+            instances,
+            raw,
+            dirty,
+            moving: None,
+        }
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.dirty = Some(union(self.dirty.take(), range));
+    }
+
+    fn mark_moving(&mut self, range: Range<usize>) {
+        self.mark_dirty(range.clone());
+        self.moving = Some(union(self.moving.take(), range));
+    }
+
+    // Everything has to be uploaded again, e.g. into a new buffer
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = Some(0..self.raw.len());
+    }
+
+    // The range of raw that changed since the last call, without anything
+    // that was removed since
+    pub fn take_dirty(&mut self) -> Option<Range<usize>> {
+        let dirty = self.dirty.take()?;
+        let end = dirty.end.min(self.raw.len());
+        (dirty.start < end).then_some(dirty.start..end)
+    }
+
+    // Call before each simulation step. Anything that moved in the last step
+    // is rendered exactly where it ended up until it moves again.
+    pub fn begin_step(&mut self) {
+        if let Some(moving) = self.moving.take() {
+            for i in moving.clone() {
+                self.raw[i] = self.instances[i].to_raw();
+            }
+            self.mark_dirty(moving);
+        }
+        self.previous.clone_from(&self.instances);
+    }
+
+    // Call once per frame with the fraction of a step the render is past the
+    // latest simulation state's predecessor
+    pub fn interpolate(&mut self, alpha: f32) {
+        let Some(moving) = self.moving.clone() else {
+            return;
+        };
+        for i in moving.clone() {
+            let current = &self.instances[i];
+            self.raw[i] = match self.previous.get(i) {
+                Some(previous) => previous.lerp(current, alpha).to_raw(),
+                None => current.to_raw(),
+            };
+        }
+        self.mark_dirty(moving);
+    }
+
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn raw(&self) -> &[InstanceRaw] {
+        &self.raw
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Instance> {
+        self.instances.get(index)
+    }
+
+    pub fn set(&mut self, index: usize, instance: Instance) {
+        self.raw[index] = instance.to_raw();
+        self.instances[index] = instance;
+        self.mark_moving(index..index + 1);
+    }
+
+    // Like set, but shows up right away instead of interpolating from where
+    // the instance was, for edits made outside the simulation
+    pub fn place(&mut self, index: usize, instance: Instance) {
+        if let Some(previous) = self.previous.get_mut(index) {
+            *previous = instance.clone();
+        }
+        self.raw[index] = instance.to_raw();
+        self.instances[index] = instance;
+        self.mark_dirty(index..index + 1);
+    }
+
+    pub fn add(&mut self, instance: Instance) -> usize {
+        let index = self.instances.len();
+        self.raw.push(instance.to_raw());
+        self.previous.push(instance.clone());
+        self.instances.push(instance);
+        self.mark_dirty(index..index + 1);
+        index
+    }
+
+    // Swaps the last instance into the removed slot, so indices past it aren't stable
+    pub fn remove(&mut self, index: usize) -> Instance {
+        let removed = self.instances.swap_remove(index);
+        self.raw.swap_remove(index);
+        if index < self.previous.len() {
+            self.previous.swap_remove(index);
+        }
+        // the instance swapped into index may have been moving
+        if let Some(moving) = self.moving.take() {
+            let moving = union(Some(moving), index..index + 1);
+            let end = moving.end.min(self.instances.len());
+            self.moving = (moving.start < end).then_some(moving.start..end);
+        }
+        if index < self.raw.len() {
+            self.mark_dirty(index..index + 1);
+        }
+        removed
+    }
+
+    // Undoes remove: puts instance back at index and the one that was
+    // swapped into its slot back at the end
+    pub fn insert(&mut self, index: usize, instance: Instance) {
+        let last = self.add(instance);
+        self.instances.swap(index, last);
+        self.raw.swap(index, last);
+        if last < self.previous.len() {
+            self.previous.swap(index, last);
+        }
+        if let Some(moving) = self.moving.take() {
+            self.moving = Some(union(Some(moving), index..last + 1));
+        }
+        self.mark_dirty(index..last + 1);
+    }
+
+    // Edit every instance in place, marks the whole buffer dirty
+    pub fn update_all<F: FnMut(usize, &mut Instance)>(&mut self, mut f: F) {
+        for (i, (instance, raw)) in self.instances.iter_mut().zip(self.raw.iter_mut()).enumerate() {
+            f(i, instance);
+            *raw = instance.to_raw();
+        }
+        self.mark_moving(0..self.raw.len());
+    }
+
+    pub fn replace_all(&mut self, instances: Vec<Instance>) {
+        *self = Self::new(instances);
+    }
+}
+
+// An InstanceList mirrored into a growable GPU buffer, update() uploads
+// what changed
+pub struct InstanceManager {
+    list: InstanceList,
+    buffer: wgpu::Buffer,
+    // in instances
+    capacity: usize,
+}
+
+impl InstanceManager {
+    pub fn new(device: &wgpu::Device, instances: Vec<Instance>) -> Self {
+        let capacity = instances.len().max(16).next_power_of_two();
+        Self {
+            list: InstanceList::new(instances),
+            buffer: Self::create_buffer(device, capacity),
+            capacity,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            // STORAGE since the GPU culling pass reads it
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn begin_step(&mut self) {
+        self.list.begin_step();
+    }
+
+    pub fn interpolate(&mut self, alpha: f32) {
+        self.list.interpolate(alpha);
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        self.list.instances()
+    }
+
+    pub fn raw(&self) -> &[InstanceRaw] {
+        self.list.raw()
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Instance> {
+        self.list.get(index)
+    }
+
+    pub fn set(&mut self, index: usize, instance: Instance) {
+        self.list.set(index, instance);
+    }
+
+    pub fn place(&mut self, index: usize, instance: Instance) {
+        self.list.place(index, instance);
+    }
+
+    pub fn add(&mut self, instance: Instance) -> usize {
+        self.list.add(instance)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Instance {
+        self.list.remove(index)
+    }
+
+    pub fn insert(&mut self, index: usize, instance: Instance) {
+        self.list.insert(index, instance);
+    }
+
+    pub fn update_all<F: FnMut(usize, &mut Instance)>(&mut self, f: F) {
+        self.list.update_all(f);
+    }
+
+    pub fn replace_all(&mut self, instances: Vec<Instance>) {
+        self.list.replace_all(instances);
+    }
+
+    // Uploads pending edits. Returns true when the buffer had to be recreated,
+    // anything holding a bind group to it has to rebuild it.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let mut recreated = false;
+        if self.list.len() > self.capacity {
+            self.capacity = self.list.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+            self.list.mark_all_dirty();
+            recreated = true;
+        }
+        if let Some(dirty) = self.list.take_dirty() {
+            let offset = (dirty.start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+            queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&self.list.raw()[dirty]));
+        }
+        recreated
+    }
+}
+
+// Where an instance index ends up after InstanceManager::remove(removed),
+// with last the index of what was the last instance. None for the removed one.
+pub fn index_after_remove(index: usize, removed: usize, last: usize) -> Option<usize> {
+    if index == removed {
+        None
+    } else if index == last {
+        Some(removed)
+    } else {
+        Some(index)
+    }
+}
+
+// Where an instance index ends up after InstanceManager::insert(inserted),
+// which moves the instance that was at inserted to last
+pub fn index_after_insert(index: usize, inserted: usize, last: usize) -> usize {
+    if index == inserted { last } else { index }
+}
+
+fn union(range: Option<Range<usize>>, other: Range<usize>) -> Range<usize> {
+    match range {
+        Some(range) => range.start.min(other.start)..range.end.max(other.end),
+        None => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(count: usize) -> Vec<Instance> {
+        (0..count).map(|x| Instance::new(cgmath::Vector3::new(x as f32, 0.0, 0.0))).collect()
+    }
+
+    fn xs(instances: &[Instance]) -> Vec<f32> {
+        instances.iter().map(|instance| instance.position.x).collect()
+    }
+
+    #[test]
+    fn edits_mark_only_the_touched_range_dirty() {
+        let mut list = InstanceList::new(row(4));
+        assert_eq!(list.take_dirty(), Some(0..4));
+        assert_eq!(list.take_dirty(), None);
+
+        list.set(2, Instance::new(cgmath::Vector3::new(9.0, 0.0, 0.0)));
+        assert_eq!(list.add(Instance::new(cgmath::Vector3::new(4.0, 0.0, 0.0))), 4);
+        assert_eq!(list.moving, Some(2..3));
+        assert_eq!(list.take_dirty(), Some(2..5));
+
+        // the moved instance is uploaded once more where it ended up
+        list.begin_step();
+        assert_eq!(list.moving, None);
+        assert_eq!(list.take_dirty(), Some(2..3));
+
+        // the last instance fills the removed slot, removing the last one
+        // leaves nothing to upload
+        list.remove(1);
+        assert_eq!(list.take_dirty(), Some(1..2));
+        list.remove(3);
+        assert_eq!(list.take_dirty(), None);
+        assert_eq!(xs(list.instances()), [0.0, 4.0, 9.0]);
+
+        // a range that was dirty before a removal is cut to what's left
+        list.set(2, Instance::new(cgmath::Vector3::new(5.0, 0.0, 0.0)));
+        list.remove(2);
+        assert_eq!(list.take_dirty(), None);
+        list.mark_all_dirty();
+        assert_eq!(list.take_dirty(), Some(0..2));
+    }
+
+    #[test]
+    fn insert_undoes_remove() {
+        let mut list = InstanceList::new(row(5));
+        list.take_dirty();
+        list.begin_step();
+        // the last instance is mid-move when it gets swapped into the gap
+        list.set(4, Instance::new(cgmath::Vector3::new(8.0, 0.0, 0.0)));
+
+        let removed = list.remove(1);
+        assert_eq!(xs(list.instances()), [0.0, 8.0, 2.0, 3.0]);
+        assert_eq!(xs(&list.previous), [0.0, 4.0, 2.0, 3.0]);
+        assert_eq!(list.moving, Some(1..4));
+
+        list.insert(1, removed);
+        assert_eq!(xs(list.instances()), [0.0, 1.0, 2.0, 3.0, 8.0]);
+        assert_eq!(xs(&list.previous), [0.0, 1.0, 2.0, 3.0, 4.0]);
+        let raw = list.instances().iter().map(|instance| instance.to_raw().model).collect::<Vec<_>>();
+        assert_eq!(list.raw().iter().map(|raw| raw.model).collect::<Vec<_>>(), raw);
+        assert!(list.moving.as_ref().is_some_and(|moving| moving.contains(&4)));
+        assert_eq!(list.take_dirty(), Some(1..5));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn buffer_grows_past_its_capacity() {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+            .expect("no adapter");
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).unwrap();
+        let mut manager = InstanceManager::new(&device, row(16));
+        assert!(!manager.update(&device, &queue));
+        manager.add(Instance::new(cgmath::Vector3::new(0.0, 0.0, 0.0)));
+        assert!(manager.update(&device, &queue));
+        assert_eq!(manager.capacity, 32);
+        assert!(!manager.update(&device, &queue));
+    }
+}
@@ -0,0 +1,47 @@
+//! Model viewer built on wgpu, usable as a library.
+//!
+//! A [`Renderer`] draws into a [`RenderTarget`]: a winit window, an offscreen
+//! texture for headless use, or a texture view owned by the embedding app.
+//! Models are loaded from .obj files with [`Renderer::load_model`] or
+//! [`load_model_obj`], and shown as a [`Scene`] of instances with
+//! [`Renderer::set_scene`]. The event loop stays with the caller: forward
+//! window events to [`Renderer::input`], then call [`Renderer::update`] and
+//! [`Renderer::render`] once per frame. The `scene` example does this for a
+//! ring of cubes, the `headless` example renders a frame without a window.
+
+pub mod state;
+pub mod render_target;
+pub mod viewport;
+pub mod scene;
+pub mod texture;
+pub mod camera;
+pub mod camera_path;
+pub mod capture;
+pub mod transformation;
+pub mod instancing;
+pub mod model;
+pub mod resources;
+pub mod debug_view;
+pub mod debug_draw;
+pub mod gizmo;
+pub mod history;
+pub mod input_map;
+pub mod bounds;
+pub mod culling;
+pub mod picking;
+pub mod raycast;
+pub mod selection;
+pub mod gpu_culling;
+pub mod stats;
+pub mod frame_limiter;
+pub mod timestep;
+
+pub use camera::{Camera, CameraMode, Projection};
+pub use instancing::Instance;
+pub use model::{Material, Mesh, Model, TextureSlot};
+pub use picking::Pick;
+pub use raycast::{Hit, Ray};
+pub use render_target::{RenderTarget, TextureTarget, ViewTarget, WindowTarget};
+pub use resources::{load_model_geometry, load_model_info, load_model_obj, load_texture, ModelInfo, ModelPath};
+pub use scene::Scene;
+pub use state::{Light, Renderer, RendererOptions};
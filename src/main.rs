@@ -1,12 +1,41 @@
+use clap::Parser;
+
+use wgpu_testing::capture::RenderJob;
+use wgpu_testing::ModelPath;
+
+use cli::{Cli, Command, RunOptions};
+
+mod cli;
 mod engine;
-mod state;
-mod texture;
-mod camera;
-mod transformation;
-mod instancing;
-mod model;
-mod resources;
-
-fn main() {
-    pollster::block_on(engine::run());
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let options = match cli.command {
+        None => RunOptions { model: ModelPath::or_default(None), window: cli.window, render: None },
+        Some(Command::View { model }) => RunOptions {
+            model: ModelPath::or_default(model.as_deref()),
+            window: cli.window,
+            render: None,
+        },
+        Some(Command::Render { scene, out, camera_path, frames, fps, encoder }) => RunOptions {
+            model: ModelPath::new(&scene),
+            window: cli.window,
+            render: Some(RenderJob { out, camera_path, frames, fps, encoder }),
+        },
+        Some(Command::Info { model }) => return cli::print_info(&model),
+        Some(Command::Validate { model }) => {
+            if !cli::validate(&model)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Bindings { file }) => {
+            if !cli::print_bindings(file.as_deref())? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    };
+    pollster::block_on(engine::run(options));
+    Ok(())
 }
@@ -1,4 +1,8 @@
 use std::ops::Range;
+use wgpu::util::DeviceExt;
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::instancing::InstanceRaw;
+use crate::raycast::{raycast_meshes, Hit, MeshGeometry, Ray};
 use crate::texture::Texture;
 
 pub trait Vertex {
@@ -52,19 +56,45 @@ impl Vertex for ModelVertex {
     }
 }
  
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub id: u32,
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    _padding: [u32; 3],
+}
+
 pub struct Material {
     pub name: String,
+    pub id: u32,
     pub diffuse_texture: Texture,
     pub normal_texture: Texture,
     pub bind_group: wgpu::BindGroup,
+    // kept so the bind group can be rebuilt when a texture is swapped
+    uniform_buffer: wgpu::Buffer,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextureSlot {
+    Diffuse,
+    Normal,
 }
 
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
+    // de-indexed copy of the vertices, only built when the device can't draw
+    // with PolygonMode::Line so the wireframe view can fall back to barycentrics
+    pub wireframe_vertex_buffer: Option<wgpu::Buffer>,
+    pub num_vertices: u32,
     pub num_elements: u32,
     pub material: usize,
+    // in model space
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    // kept on the CPU for raycasts
+    pub geometry: MeshGeometry,
 }
 
 pub struct Model {
@@ -72,7 +102,31 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    pub fn aabb(&self) -> Aabb {
+        self.meshes.iter()
+            .map(|mesh| mesh.aabb)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(Aabb::from_points([cgmath::Point3::new(0.0, 0.0, 0.0)]))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        use cgmath::MetricSpace;
+        let center = self.aabb().center();
+        let radius = self.meshes.iter()
+            .map(|mesh| center.distance(mesh.bounding_sphere.center) + mesh.bounding_sphere.radius)
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    // Closest hit of a model space ray
+    pub fn raycast(&self, ray: &Ray) -> Option<Hit> {
+        raycast_meshes(self.meshes.iter().map(|mesh| &mesh.geometry), ray)
+    }
+}
+
 pub trait DrawModel<'a> {
+    #[allow(dead_code)]
     fn draw_mesh(&mut self, mesh: &'a Mesh, material: &'a Material, bind_groups: &[&'a wgpu::BindGroup]);
     fn draw_mesh_instanced(
         &mut self,
@@ -81,6 +135,7 @@ pub trait DrawModel<'a> {
         instances: Range<u32>,
         bind_groups: &[&'a wgpu::BindGroup],
     );
+    #[allow(dead_code)]
     fn draw_model(&mut self, model: &'a Model, bind_groups: &[&'a wgpu::BindGroup]);
     fn draw_model_instanced(
         &mut self,
@@ -88,6 +143,23 @@ pub trait DrawModel<'a> {
         instances: Range<u32>,
         bind_groups: &[&'a wgpu::BindGroup],
     );
+    // mesh_instances[i] is the instance range for model.meshes[i], empty ranges are skipped
+    fn draw_model_culled(
+        &mut self,
+        model: &'a Model,
+        mesh_instances: &[Range<u32>],
+        bind_groups: &[&'a wgpu::BindGroup],
+    );
+    // Draw counts come from indirect_buffer, one DrawIndexedIndirect per mesh.
+    // Mesh i reads its instances starting at i * instances_per_mesh.
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        indirect_buffer: &'a wgpu::Buffer,
+        instance_buffer: &'a wgpu::Buffer,
+        instances_per_mesh: u32,
+        bind_groups: &[&'a wgpu::BindGroup],
+    );
 }
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
 where
@@ -128,8 +200,50 @@ where
             self.draw_mesh_instanced(mesh, material, instances.clone(), bind_groups);
         }
     }
+
+    fn draw_model_culled(
+        &mut self,
+        model: &'b Model,
+        mesh_instances: &[Range<u32>],
+        bind_groups: &[&'b wgpu::BindGroup],
+    ) {
+        for (mesh, instances) in model.meshes.iter().zip(mesh_instances) {
+            if instances.is_empty() {
+                continue;
+            }
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_instanced(mesh, material, instances.clone(), bind_groups);
+        }
+    }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'b Model,
+        indirect_buffer: &'b wgpu::Buffer,
+        instance_buffer: &'b wgpu::Buffer,
+        instances_per_mesh: u32,
+        bind_groups: &[&'b wgpu::BindGroup],
+    ) {
+        let region = instances_per_mesh as wgpu::BufferAddress
+            * std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+        let args_size = std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress;
+        // first_instance stays 0 in the args, since a non-zero value needs
+        // INDIRECT_FIRST_INSTANCE, so each mesh's region is bound by offset instead
+        for (i, mesh) in model.meshes.iter().enumerate() {
+            let i = i as wgpu::BufferAddress;
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_vertex_buffer(1, instance_buffer.slice(i * region..(i + 1) * region));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.set_bind_group(0, &model.materials[mesh.material].bind_group, &[]);
+            for (i, bind_group) in bind_groups.iter().enumerate() {
+                self.set_bind_group((i + 1) as u32, bind_group, &[]);
+            }
+            self.draw_indexed_indirect(indirect_buffer, i * args_size);
+        }
+    }
 }
 
+#[allow(dead_code)]
 pub trait DrawLight<'a> {
     fn draw_light_mesh(
         &mut self,
@@ -205,38 +319,70 @@ impl Material {
     pub fn new(
         device: &wgpu::Device,
         name: &str,
+        id: u32,
         diffuse_texture: Texture,
         normal_texture: Texture, 
         layout: &wgpu::BindGroupLayout,
     ) -> Self {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
-                },
-            ],
-            label: Some(name),
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform { id, _padding: [0; 3] }]),
+            usage: wgpu::BufferUsages::UNIFORM,
         });
+        let bind_group = create_bind_group(device, name, &diffuse_texture, &normal_texture, &uniform_buffer, layout);
 
         Self {
             name: String::from(name),
+            id,
             diffuse_texture,
             normal_texture,
             bind_group,
+            uniform_buffer,
         }
     }
+
+    // Swaps texture into slot, leaving the one it replaces in texture
+    pub fn swap_texture(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, slot: TextureSlot, texture: &mut Texture) {
+        match slot {
+            TextureSlot::Diffuse => std::mem::swap(&mut self.diffuse_texture, texture),
+            TextureSlot::Normal => std::mem::swap(&mut self.normal_texture, texture),
+        }
+        self.bind_group = create_bind_group(device, &self.name, &self.diffuse_texture, &self.normal_texture, &self.uniform_buffer, layout);
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    name: &str,
+    diffuse_texture: &Texture,
+    normal_texture: &Texture,
+    uniform_buffer: &wgpu::Buffer,
+    layout: &wgpu::BindGroupLayout,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some(name),
+    })
 }
@@ -0,0 +1,43 @@
+struct Outline {
+    // in pixels
+    width: i32,
+    // alpha of the tint over selected pixels, 0 turns the overlay off
+    overlay: f32,
+}
+
+@group(0) @binding(0)
+var mask: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> outline: Outline;
+
+// one triangle covering the viewport
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(mask));
+    let pixel = vec2<i32>(position.xy);
+    let inside = textureLoad(mask, pixel, 0);
+    if inside.a > 0.0 {
+        return vec4<f32>(inside.rgb, outline.overlay);
+    }
+    // outside the mask, take the color of the closest selected pixel in reach
+    var color = vec4<f32>(0.0);
+    var closest = outline.width * outline.width + 1;
+    for (var y = -outline.width; y <= outline.width; y++) {
+        for (var x = -outline.width; x <= outline.width; x++) {
+            let distance = x * x + y * y;
+            let neighbour = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1);
+            let sample = textureLoad(mask, neighbour, 0);
+            if sample.a > 0.0 && distance < closest {
+                closest = distance;
+                color = sample;
+            }
+        }
+    }
+    return color;
+}
@@ -0,0 +1,342 @@
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use crate::camera::DepthMode;
+use crate::instancing::InstanceRaw;
+use crate::model::{Model, ModelVertex, Vertex};
+use crate::texture::Texture;
+use crate::viewport::PaneRect;
+
+pub const PICK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+// What's under the cursor, indices into the scene's instances and the model's
+// meshes and materials
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pick {
+    pub instance: usize,
+    pub mesh: usize,
+    pub material: usize,
+}
+
+// Written by pick.wgsl for every pixel of a mesh, 0 is the background
+pub fn pick_id(instance: u32, mesh: u32, meshes: u32) -> u32 {
+    instance * meshes + mesh + 1
+}
+
+// The instance and mesh of an id from pick_id
+pub fn decode_pick_id(id: u32, meshes: u32) -> Option<(usize, usize)> {
+    if id == 0 || meshes == 0 {
+        return None;
+    }
+    let index = id - 1;
+    Some(((index / meshes) as usize, (index % meshes) as usize))
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PickMeshUniform {
+    mesh: u32,
+    meshes: u32,
+    _padding: [u32; 2],
+}
+
+// Draws object ids into an R32Uint target and reads back a single pixel. Only
+// the clicked pixel is rasterized, so a pick costs little more than the
+// vertex work. Picks are asynchronous, poll() returns the id a few frames
+// after encode().
+pub struct Picker {
+    // indexed by DepthMode
+    pipeline: [wgpu::RenderPipeline; 2],
+    mesh_layout: wgpu::BindGroupLayout,
+    mesh_buffer: wgpu::Buffer,
+    mesh_bind_group: wgpu::BindGroup,
+    // PickMeshUniforms in mesh_buffer, one per dynamic offset
+    mesh_stride: u32,
+    mesh_capacity: usize,
+    id_texture: wgpu::Texture,
+    depth_texture: Texture,
+    // a single padded row
+    readback: wgpu::Buffer,
+    encoded: bool,
+    mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+impl Picker {
+    pub fn new(device: &wgpu::Device, camera_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
+        let mesh_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Pick Mesh Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<PickMeshUniform>() as u64),
+                },
+                count: None,
+            }],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Pick Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &mesh_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Pick Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("pick.wgsl").into()),
+        });
+        let pipeline = DepthMode::ALL.map(|depth_mode| device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Pick Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // integer targets can't blend
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: PICK_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_mode.compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        }));
+
+        let mesh_stride = (std::mem::size_of::<PickMeshUniform>() as u32)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment);
+        let (mesh_buffer, mesh_bind_group) = Self::create_mesh_buffer(device, &mesh_layout, mesh_stride, 1);
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Readback Buffer"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            pipeline,
+            mesh_layout,
+            mesh_buffer,
+            mesh_bind_group,
+            mesh_stride,
+            mesh_capacity: 1,
+            id_texture: Self::create_id_texture(device, 1, 1),
+            depth_texture: Texture::create_depth_texture(device, (1, 1), "pick_depth_texture"),
+            readback,
+            encoded: false,
+            mapped: None,
+        }
+    }
+
+    fn create_mesh_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        stride: u32,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Pick Mesh Buffer"),
+            size: stride as wgpu::BufferAddress * capacity as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Pick Mesh Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<PickMeshUniform>() as u64),
+                }),
+            }],
+        });
+        (buffer, bind_group)
+    }
+
+    fn create_id_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Pick Id Texture"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: PICK_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    // True from encode() until poll() has returned its id
+    pub fn is_pending(&self) -> bool {
+        self.encoded || self.mapped.is_some()
+    }
+
+    // Draws every instance as seen through camera_bind_group into rect of a
+    // target_size frame, and copies the id at (x, y) for poll()
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        (x, y): (u32, u32),
+        rect: PaneRect,
+        target_size: (u32, u32),
+        camera_bind_group: &wgpu::BindGroup,
+        depth_mode: DepthMode,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+        num_instances: u32,
+    ) {
+        if self.is_pending() {
+            return;
+        }
+        let (width, height) = (target_size.0.max(1), target_size.1.max(1));
+        if (self.id_texture.width(), self.id_texture.height()) != (width, height) {
+            self.id_texture = Self::create_id_texture(device, width, height);
+            self.depth_texture = Texture::create_depth_texture(device, (width, height), "pick_depth_texture");
+        }
+        let meshes = model.meshes.len();
+        if meshes > self.mesh_capacity {
+            self.mesh_capacity = meshes.next_power_of_two();
+            (self.mesh_buffer, self.mesh_bind_group) =
+                Self::create_mesh_buffer(device, &self.mesh_layout, self.mesh_stride, self.mesh_capacity);
+        }
+        let mut uniforms = vec![0u8; self.mesh_stride as usize * meshes];
+        for (mesh, bytes) in uniforms.chunks_exact_mut(self.mesh_stride as usize).enumerate() {
+            let uniform = PickMeshUniform { mesh: mesh as u32, meshes: meshes as u32, _padding: [0; 2] };
+            bytes[..std::mem::size_of::<PickMeshUniform>()].copy_from_slice(bytemuck::bytes_of(&uniform));
+        }
+        queue.write_buffer(&self.mesh_buffer, 0, &uniforms);
+
+        let (x, y) = (x.min(width - 1), y.min(height - 1));
+        let id_view = self.id_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Pick Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &id_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(depth_mode.clear_value()),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            render_pass.set_viewport(rect.x as f32, rect.y as f32, rect.width as f32, rect.height as f32, 0.0, 1.0);
+            render_pass.set_scissor_rect(x, y, 1, 1);
+            render_pass.set_pipeline(&self.pipeline[depth_mode as usize]);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
+            // instance_index has to be the index into the scene, so no culling
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for (i, mesh) in model.meshes.iter().enumerate() {
+                render_pass.set_bind_group(1, &self.mesh_bind_group, &[i as u32 * self.mesh_stride]);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..num_instances);
+            }
+        }
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.id_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.encoded = true;
+    }
+
+    // Call once the encoder holding encode() was submitted
+    pub fn submit(&mut self) {
+        if !std::mem::take(&mut self.encoded) {
+            return;
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.readback.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.mapped = Some(receiver);
+    }
+
+    // Call after polling the device. Returns the picked id once the readback
+    // has finished, 0 if the pick failed.
+    pub fn poll(&mut self) -> Option<u32> {
+        let mapped = self.mapped.as_ref()?;
+        let result = match mapped.try_recv() {
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => Err("readback was dropped".to_string()),
+            Ok(result) => result.map_err(|e| format!("{:?}", e)),
+        };
+        self.mapped = None;
+        match result {
+            Ok(()) => {
+                let id = {
+                    let data = self.readback.slice(..).get_mapped_range();
+                    u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+                };
+                self.readback.unmap();
+                Some(id)
+            }
+            Err(e) => {
+                println!("Pick failed: {}", e);
+                Some(0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pick_ids_round_trip() {
+        assert_eq!(decode_pick_id(0, 3), None);
+        for (instance, mesh) in [(0, 0), (0, 2), (7, 1), (9999, 2)] {
+            let id = pick_id(instance, mesh, 3);
+            assert_eq!(decode_pick_id(id, 3), Some((instance as usize, mesh as usize)));
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use cgmath::*;
+
+use crate::bounds::Aabb;
+use crate::camera::{Camera, Projection};
+
+// Triangles per BVH leaf
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    // doesn't have to be normalized, hit distances are in multiples of it
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self { origin, direction }
+    }
+
+    // The same ray in the space matrix maps to, distances along it stay the same
+    pub fn transform(&self, matrix: &Matrix4<f32>) -> Self {
+        Self {
+            origin: matrix.transform_point(self.origin),
+            direction: matrix.transform_vector(self.direction),
+        }
+    }
+
+    // Through pixel (x, y) of a width x height view, from where the camera
+    // sees it. The direction has unit length.
+    pub fn through_pixel(camera: &Camera, projection: &Projection, (x, y): (f32, f32), (width, height): (f32, f32)) -> Option<Self> {
+        let to_world = (projection.calc_matrix() * camera.calc_matrix()).invert()?;
+        let ndc = Vector2::new(x / width * 2.0 - 1.0, 1.0 - y / height * 2.0);
+        // both depths are finite with reverse-z's infinite far plane too
+        let [a, b] = [0.5, 0.9].map(|depth| {
+            let clip = to_world * Vector4::new(ndc.x, ndc.y, depth, 1.0);
+            Point3::from_homogeneous(clip)
+        });
+        let direction = (b - a).normalize();
+        // which depth is closer depends on the depth mode
+        let direction = if direction.dot(camera.forward()) < 0.0 { -direction } else { direction };
+        Some(Self::new(a, direction))
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // Distance to where the ray enters aabb, 0 if it starts inside
+    fn intersect_aabb(&self, aabb: &Aabb, inverse_direction: Vector3<f32>, max_distance: f32) -> Option<f32> {
+        let mut near = 0.0f32;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let t0 = (aabb.min[axis] - self.origin[axis]) * inverse_direction[axis];
+            let t1 = (aabb.max[axis] - self.origin[axis]) * inverse_direction[axis];
+            // NaN from 0 * inf, a ray in the slab's plane, fails neither test
+            near = near.max(t0.min(t1));
+            far = far.min(t0.max(t1));
+        }
+        (near <= far).then_some(near)
+    }
+
+    // Möller-Trumbore, returns the distance and the barycentrics of b and c.
+    // Hits from both sides count.
+    fn intersect_triangle(&self, [a, b, c]: [Point3<f32>; 3]) -> Option<(f32, f32, f32)> {
+        let ab = b - a;
+        let ac = c - a;
+        let p = self.direction.cross(ac);
+        let determinant = ab.dot(p);
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+        let inverse_determinant = 1.0 / determinant;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = to_origin.cross(ab);
+        let v = self.direction.dot(q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = ac.dot(q) * inverse_determinant;
+        (distance >= 0.0).then_some((distance, u, v))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub distance: f32,
+    pub point: Point3<f32>,
+    // index into the model's meshes
+    pub mesh: usize,
+    // index of the triangle's first index divided by 3
+    pub triangle: usize,
+    // weights of the triangle's three vertices at point
+    pub barycentrics: [f32; 3],
+    pub material: usize,
+}
+
+#[derive(Clone, Debug)]
+struct BvhNode {
+    aabb: Aabb,
+    // leaves hold count triangles from first on, inner nodes have count 0
+    // and their children at first and first + 1
+    first: usize,
+    count: usize,
+}
+
+// CPU copy of a mesh's positions and indices with a BVH over its triangles
+pub struct MeshGeometry {
+    pub positions: Vec<Point3<f32>>,
+    pub indices: Vec<u32>,
+    pub material: usize,
+    nodes: Vec<BvhNode>,
+    // triangle indices, reordered so every leaf's are contiguous
+    triangles: Vec<usize>,
+}
+
+impl MeshGeometry {
+    pub fn new(positions: Vec<Point3<f32>>, indices: Vec<u32>, material: usize) -> Self {
+        let mut geometry = Self {
+            positions,
+            indices,
+            material,
+            nodes: Vec::new(),
+            triangles: Vec::new(),
+        };
+        geometry.build_bvh();
+        geometry
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangle(&self, triangle: usize) -> [Point3<f32>; 3] {
+        let index = |i: usize| self.positions[self.indices[triangle * 3 + i] as usize];
+        [index(0), index(1), index(2)]
+    }
+
+    // Splits at the median centroid along the longest axis of the centroids'
+    // box until leaves are small enough
+    fn build_bvh(&mut self) {
+        self.triangles = (0..self.num_triangles()).collect();
+        let centroids = (0..self.num_triangles())
+            .map(|triangle| {
+                let [a, b, c] = self.triangle(triangle);
+                Point3::from_vec((a.to_vec() + b.to_vec() + c.to_vec()) / 3.0)
+            })
+            .collect::<Vec<_>>();
+        self.nodes = vec![BvhNode { aabb: self.triangles_aabb(0..self.triangles.len()), first: 0, count: self.triangles.len() }];
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let BvhNode { first, count, .. } = self.nodes[node];
+            if count <= MAX_LEAF_TRIANGLES {
+                continue;
+            }
+            let bounds = Aabb::from_points(self.triangles[first..first + count].iter().map(|&t| centroids[t]));
+            let extent = bounds.max - bounds.min;
+            let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            };
+            let half = count / 2;
+            self.triangles[first..first + count].select_nth_unstable_by(half, |&a, &b| {
+                centroids[a][axis].total_cmp(&centroids[b][axis])
+            });
+            let left = self.nodes.len();
+            for range in [first..first + half, first + half..first + count] {
+                self.nodes.push(BvhNode { aabb: self.triangles_aabb(range.clone()), first: range.start, count: range.len() });
+            }
+            self.nodes[node].first = left;
+            self.nodes[node].count = 0;
+            stack.extend([left, left + 1]);
+        }
+    }
+
+    fn triangles_aabb(&self, range: std::ops::Range<usize>) -> Aabb {
+        Aabb::from_points(self.triangles[range].iter().flat_map(|&t| self.triangle(t)))
+    }
+
+    // Closest hit in model space, mesh is left at 0
+    pub fn raycast(&self, ray: &Ray) -> Option<Hit> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let inverse_direction = Vector3::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut closest: Option<(f32, usize, f32, f32)> = None;
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            let max_distance = closest.map_or(f32::INFINITY, |(distance, ..)| distance);
+            if ray.intersect_aabb(&node.aabb, inverse_direction, max_distance).is_none() {
+                continue;
+            }
+            if node.count == 0 {
+                stack.extend([node.first, node.first + 1]);
+                continue;
+            }
+            for &triangle in &self.triangles[node.first..node.first + node.count] {
+                if let Some((distance, u, v)) = ray.intersect_triangle(self.triangle(triangle)) {
+                    if distance < max_distance && closest.is_none_or(|(closest, ..)| distance < closest) {
+                        closest = Some((distance, triangle, u, v));
+                    }
+                }
+            }
+        }
+        closest.map(|(distance, triangle, u, v)| Hit {
+            distance,
+            point: ray.at(distance),
+            mesh: 0,
+            triangle,
+            barycentrics: [1.0 - u - v, u, v],
+            material: self.material,
+        })
+    }
+}
+
+// Closest hit over several meshes, Hit::mesh is the index into meshes
+pub fn raycast_meshes<'a>(meshes: impl IntoIterator<Item = &'a MeshGeometry>, ray: &Ray) -> Option<Hit> {
+    meshes.into_iter()
+        .enumerate()
+        .filter_map(|(mesh, geometry)| geometry.raycast(ray).map(|hit| Hit { mesh, ..hit }))
+        .min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::load_model_geometry;
+
+    fn brute_force(geometry: &MeshGeometry, ray: &Ray) -> Option<f32> {
+        (0..geometry.num_triangles())
+            .filter_map(|triangle| ray.intersect_triangle(geometry.triangle(triangle)))
+            .map(|(distance, ..)| distance)
+            .min_by(f32::total_cmp)
+    }
+
+    #[test]
+    fn rays_hit_the_cube() {
+        let meshes = load_model_geometry("cube.obj", "cube").unwrap();
+        let aabb = Aabb::from_points(meshes[0].positions.iter().copied());
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, 10.0), Vector3::new(0.0, 0.0, -1.0));
+        let hit = raycast_meshes(&meshes, &ray).unwrap();
+        assert_eq!(hit.mesh, 0);
+        assert!((hit.distance - (10.0 - aabb.max.z)).abs() < 1e-4);
+        assert!((hit.barycentrics.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        let [a, b, c] = meshes[0].triangle(hit.triangle);
+        let interpolated = a.to_vec() * hit.barycentrics[0] + b.to_vec() * hit.barycentrics[1] + c.to_vec() * hit.barycentrics[2];
+        assert!((Point3::from_vec(interpolated) - hit.point).magnitude() < 1e-4);
+
+        assert!(raycast_meshes(&meshes, &Ray::new(Point3::new(5.0, 0.0, 10.0), Vector3::new(0.0, 0.0, -1.0))).is_none());
+        // pointing away
+        assert!(raycast_meshes(&meshes, &Ray::new(Point3::new(0.0, 0.0, 10.0), Vector3::new(0.0, 0.0, 1.0))).is_none());
+    }
+
+    #[test]
+    fn pixel_rays_leave_the_camera() {
+        let camera = Camera::new((1.0, 2.0, 3.0), Deg(-90.0), Deg(-20.0));
+        let mut projection = Projection::new(800, 600, Deg(45.0), 0.1, 100.0);
+        for depth_mode in crate::camera::DepthMode::ALL {
+            projection.set_depth_mode(depth_mode);
+            let center = Ray::through_pixel(&camera, &projection, (400.0, 300.0), (800.0, 600.0)).unwrap();
+            assert!(center.direction.dot(camera.forward()) > 0.9999);
+            assert!((center.origin - camera.position).cross(camera.forward()).magnitude() < 1e-3);
+            // the top left corner is up and to the left of the view direction
+            let corner = Ray::through_pixel(&camera, &projection, (0.0, 0.0), (800.0, 600.0)).unwrap();
+            let right = camera.forward().cross(Vector3::unit_y());
+            assert!(corner.direction.dot(right) < 0.0);
+            assert!(corner.direction.y > center.direction.y);
+        }
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let meshes = load_model_geometry("cube.obj", "cube").unwrap();
+        let geometry = &meshes[0];
+        // rays from a sphere around the cube towards points near its center
+        let mut hits = 0;
+        for i in 0..200 {
+            let (theta, phi) = (i as f32 * 2.399, (i as f32 * 0.7).sin() * 1.5);
+            let origin = Point3::new(theta.cos() * phi.cos(), phi.sin(), theta.sin() * phi.cos()) * 5.0;
+            let target = Point3::new((i as f32 * 1.3).sin(), (i as f32 * 2.1).cos(), (i as f32 * 0.9).sin()) * 1.2;
+            let ray = Ray::new(origin, target - origin);
+            let expected = brute_force(geometry, &ray);
+            let hit = geometry.raycast(&ray).map(|hit| hit.distance);
+            match (expected, hit) {
+                (Some(expected), Some(hit)) => {
+                    assert!((expected - hit).abs() < 1e-5, "ray {}: {} vs {}", i, expected, hit);
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("ray {}: brute force {:?}, bvh {:?}", i, expected, hit),
+            }
+        }
+        assert!(hits > 100, "only {} of 200 rays hit", hits);
+    }
+}
@@ -0,0 +1,279 @@
+use winit::window::Window;
+
+// Where the renderer draws a frame. Each frame is bracketed by begin_frame and
+// end_frame, view() and texture() are only valid in between.
+pub trait RenderTarget {
+    fn size(&self) -> (u32, u32);
+    // The renderer's pipelines are built for this, it can't change later
+    fn format(&self) -> wgpu::TextureFormat;
+    // Frame captures and screenshots need COPY_SRC
+    fn usage(&self) -> wgpu::TextureUsages;
+    // Zero sizes are ignored
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32);
+    fn begin_frame(&mut self, device: &wgpu::Device) -> Result<(), wgpu::SurfaceError>;
+    fn view(&self) -> &wgpu::TextureView;
+    // None if the target only has a view, which can't be copied from
+    fn texture(&self) -> Option<&wgpu::Texture>;
+    // Presents window targets, called after the frame was submitted
+    fn end_frame(&mut self);
+
+    // Only windows present, other targets have no present modes
+    fn present_modes(&self) -> &[wgpu::PresentMode] {
+        &[]
+    }
+    fn present_mode(&self) -> Option<wgpu::PresentMode> {
+        None
+    }
+    fn set_present_mode(&mut self, _device: &wgpu::Device, _present_mode: wgpu::PresentMode) -> bool {
+        false
+    }
+    fn window(&self) -> Option<&Window> {
+        None
+    }
+}
+
+// A winit window's swapchain
+pub struct WindowTarget {
+    // dropped before the window it was created from
+    surface: wgpu::Surface,
+    window: Window,
+    config: wgpu::SurfaceConfiguration,
+    // what the surface supports, Fifo always is
+    present_modes: Vec<wgpu::PresentMode>,
+    frame: Option<(wgpu::SurfaceTexture, wgpu::TextureView)>,
+}
+
+impl WindowTarget {
+    // The surface has to be created from window before the adapter is picked,
+    // so the adapter can be asked to be compatible with it. format is used
+    // instead of the surface's preferred sRGB format if it's supported, so
+    // windows can share pipelines.
+    pub fn new(
+        window: Window,
+        surface: wgpu::Surface,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+        vsync: bool,
+        format: Option<wgpu::TextureFormat>,
+    ) -> Self {
+        let size = window.inner_size();
+        let surface_caps = surface.get_capabilities(adapter);
+        let surface_format = format
+            .filter(|format| surface_caps.formats.contains(format))
+            .or_else(|| surface_caps.formats.iter().copied().find(|format| format.is_srgb()))
+            .unwrap_or(surface_caps.formats[0]);
+        // without vsync prefer tearing over Mailbox's extra latency
+        let present_mode = if vsync {
+            wgpu::PresentMode::Fifo
+        } else {
+            [wgpu::PresentMode::Immediate, wgpu::PresentMode::Mailbox]
+                .into_iter()
+                .find(|mode| surface_caps.present_modes.contains(mode))
+                .unwrap_or(wgpu::PresentMode::Fifo)
+        };
+        let config = wgpu::SurfaceConfiguration {
+            // COPY_SRC for frame capture, where the surface supports it
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode,
+            alpha_mode: surface_caps.alpha_modes[0],
+            view_formats: vec![],
+        };
+        surface.configure(device, &config);
+        Self {
+            surface,
+            window,
+            config,
+            present_modes: surface_caps.present_modes,
+            frame: None,
+        }
+    }
+}
+
+impl RenderTarget for WindowTarget {
+    fn size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+
+    fn format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    fn usage(&self) -> wgpu::TextureUsages {
+        self.config.usage
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.config.width = width;
+            self.config.height = height;
+            self.surface.configure(device, &self.config);
+        }
+    }
+
+    fn begin_frame(&mut self, _device: &wgpu::Device) -> Result<(), wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.frame = Some((output, view));
+        Ok(())
+    }
+
+    fn view(&self) -> &wgpu::TextureView {
+        &self.frame.as_ref().expect("view() outside of a frame").1
+    }
+
+    fn texture(&self) -> Option<&wgpu::Texture> {
+        self.frame.as_ref().map(|(output, _)| &output.texture)
+    }
+
+    fn end_frame(&mut self) {
+        if let Some((output, _)) = self.frame.take() {
+            output.present();
+        }
+    }
+
+    fn present_modes(&self) -> &[wgpu::PresentMode] {
+        &self.present_modes
+    }
+
+    fn present_mode(&self) -> Option<wgpu::PresentMode> {
+        Some(self.config.present_mode)
+    }
+
+    fn set_present_mode(&mut self, device: &wgpu::Device, present_mode: wgpu::PresentMode) -> bool {
+        if !self.present_modes.contains(&present_mode) {
+            return false;
+        }
+        self.config.present_mode = present_mode;
+        self.surface.configure(device, &self.config);
+        true
+    }
+
+    fn window(&self) -> Option<&Window> {
+        Some(&self.window)
+    }
+}
+
+// An offscreen texture, for headless rendering or sampling the frame elsewhere
+pub struct TextureTarget {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl TextureTarget {
+    pub const USAGE: wgpu::TextureUsages = wgpu::TextureUsages::RENDER_ATTACHMENT
+        .union(wgpu::TextureUsages::COPY_SRC)
+        .union(wgpu::TextureUsages::TEXTURE_BINDING);
+
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Render Target Texture"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: Self::USAGE,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+}
+
+impl RenderTarget for TextureTarget {
+    fn size(&self) -> (u32, u32) {
+        (self.texture.width(), self.texture.height())
+    }
+
+    fn format(&self) -> wgpu::TextureFormat {
+        self.texture.format()
+    }
+
+    fn usage(&self) -> wgpu::TextureUsages {
+        Self::USAGE
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if width > 0 && height > 0 && (width, height) != self.size() {
+            *self = Self::new(device, width, height, self.format());
+        }
+    }
+
+    fn begin_frame(&mut self, _device: &wgpu::Device) -> Result<(), wgpu::SurfaceError> {
+        Ok(())
+    }
+
+    fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    fn texture(&self) -> Option<&wgpu::Texture> {
+        Some(&self.texture)
+    }
+
+    fn end_frame(&mut self) {}
+}
+
+// A view owned by the embedding app, handed over with set_view before each
+// frame. Its size follows the view, resize() does nothing.
+pub struct ViewTarget {
+    view: Option<wgpu::TextureView>,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl ViewTarget {
+    pub fn new(width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        Self { view: None, width, height, format }
+    }
+
+    // The view has to be of format and have RENDER_ATTACHMENT
+    pub fn set_view(&mut self, view: wgpu::TextureView, width: u32, height: u32) {
+        self.view = Some(view);
+        self.width = width;
+        self.height = height;
+    }
+}
+
+impl RenderTarget for ViewTarget {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    fn usage(&self) -> wgpu::TextureUsages {
+        wgpu::TextureUsages::RENDER_ATTACHMENT
+    }
+
+    fn resize(&mut self, _device: &wgpu::Device, _width: u32, _height: u32) {}
+
+    // Nothing to draw into until set_view, the frame fails like a lost surface would
+    fn begin_frame(&mut self, _device: &wgpu::Device) -> Result<(), wgpu::SurfaceError> {
+        match self.view {
+            Some(_) => Ok(()),
+            None => Err(wgpu::SurfaceError::Lost),
+        }
+    }
+
+    fn view(&self) -> &wgpu::TextureView {
+        self.view.as_ref().expect("begin_frame checks for a view")
+    }
+
+    fn texture(&self) -> Option<&wgpu::Texture> {
+        None
+    }
+
+    fn end_frame(&mut self) {}
+}
@@ -1,7 +1,11 @@
 use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use anyhow::Context;
 use wgpu::util::DeviceExt;
 
 use crate::{model, texture};
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::raycast::MeshGeometry;
 use crate::texture::generate_placeholder_texture;
 
 /*#[cfg(target_arch = "wasm32")]
@@ -16,11 +20,41 @@ fn format_url(file_name: &str) -> reqwest::Url {
     base.join(file_name).unwrap()
 }*/
 
-pub async fn load_string(file_name: &str, subfolder: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
+const DEFAULT_MODEL: &str = "raphtalia/raphtalia.obj";
+
+// load_model_obj takes a file name and a folder inside res/. Paths that exist
+// on disk are passed as an absolute folder instead, which Path::join keeps.
+#[derive(Clone, Debug)]
+pub struct ModelPath {
+    pub file_name: String,
+    pub subfolder: String,
+}
+
+impl ModelPath {
+    pub fn new(path: &Path) -> Self {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        Self {
+            file_name: path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+            subfolder: path.parent().map_or(String::new(), |parent| parent.to_string_lossy().into_owned()),
+        }
+    }
+
+    // The model the viewer opens when none is given
+    pub fn or_default(path: Option<&Path>) -> Self {
+        Self::new(path.unwrap_or(Path::new(DEFAULT_MODEL)))
+    }
+}
+
+// An absolute subfolder replaces the res/ folder entirely
+fn resource_path(file_name: &str, subfolder: &str) -> PathBuf {
+    Path::new(env!("OUT_DIR"))
         .join("res")
         .join(subfolder)
-        .join(file_name);
+        .join(file_name)
+}
+
+pub async fn load_string(file_name: &str, subfolder: &str) -> anyhow::Result<String> {
+    let path = resource_path(file_name, subfolder);
     if !path.exists() {
         panic!("File at {:?} does not exist", path);
     }
@@ -30,10 +64,7 @@ pub async fn load_string(file_name: &str, subfolder: &str) -> anyhow::Result<Str
 }
 
 pub async fn load_binary(file_name: &str, subfolder: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(env!("OUT_DIR"))
-        .join("res")
-        .join(subfolder)
-        .join(file_name);
+    let path = resource_path(file_name, subfolder);
     if !path.exists() {
         panic!("File at {:?} does not exist", path);
     }
@@ -58,89 +89,113 @@ pub async fn load_texture(
     }
 }
 
-pub async fn load_model_obj(
-    file_name: &str,
-    subfolder: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<model::Model> {
-    let obj_text = load_string(file_name, subfolder).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
+// map_Bump lines usually carry options before the file name, e.g. "-bm 1 normal.png"
+fn normal_texture_file(map_bump: &str) -> &str {
+    map_bump.rsplit_once(' ').map_or(map_bump, |(_, file)| file)
+}
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
-        &mut obj_reader,
+// Parsed .obj file with its material libraries. Every loader goes through
+// here so mesh and material indices mean the same thing to all of them.
+struct ObjFile {
+    models: Vec<tobj::Model>,
+    materials: Result<Vec<tobj::Material>, tobj::LoadError>,
+    // material libraries that are referenced but don't exist
+    missing_libraries: Vec<PathBuf>,
+}
+
+fn load_obj(file_name: &str, subfolder: &str) -> anyhow::Result<ObjFile> {
+    let path = resource_path(file_name, subfolder);
+    let obj_text = std::fs::read_to_string(&path)
+        .with_context(|| format!("reading {}", path.display()))?;
+    // the material loader has to be Fn
+    let missing_libraries = std::cell::RefCell::new(Vec::new());
+    let (models, materials) = tobj::load_obj_buf(
+        &mut BufReader::new(Cursor::new(obj_text)),
         &tobj::LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p, subfolder).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let mtl_path = resource_path(&p.to_string_lossy(), subfolder);
+            match std::fs::read_to_string(&mtl_path) {
+                Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                Err(_) => {
+                    missing_libraries.borrow_mut().push(mtl_path);
+                    Err(tobj::LoadError::OpenFileFailed)
+                }
+            }
         },
-    ).await?;
+    )?;
+    Ok(ObjFile { models, materials, missing_libraries: missing_libraries.into_inner() })
+}
+
+pub async fn load_model_obj(
+    file_name: &str,
+    subfolder: &str,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<model::Model> {
+    let ObjFile { models, materials: mats, .. } = load_obj(file_name, subfolder)?;
 
     let mut materials = Vec::new();
-    let mats = obj_materials?;
+    let mats = mats?;
     for m in &mats {
-        let diffuse_texture: texture::Texture;
-        let normal_texture: texture::Texture; 
-
-        if m.diffuse_texture.is_some() {
-            println!("Loading texture: {}", m.diffuse_texture.as_ref().unwrap());
-            diffuse_texture = load_texture(m.diffuse_texture.as_ref().unwrap(), subfolder, false, device, queue).await?;
+        let diffuse_texture = if let Some(diffuse_path) = &m.diffuse_texture {
+            println!("Loading texture: {}", diffuse_path);
+            load_texture(diffuse_path, subfolder, false, device, queue).await?
         } else if let Some(diffuse) = m.diffuse { 
             println!("Loading BDSF diffuse: color ");
-            diffuse_texture = generate_placeholder_texture(device,
-                                                           queue,
-                                                           128,
-                                                           128,
-                                                           false,
-                                                           image::Rgba([
-                                                                       (diffuse[0] * 255.0) as u8,
-                                                                       (diffuse[1] * 255.0) as u8,
-                                                                       (diffuse[2] * 255.0) as u8,
-                                                                       255]
-                                                           ));
+            generate_placeholder_texture(device,
+                                         queue,
+                                         128,
+                                         128,
+                                         false,
+                                         image::Rgba([
+                                                     (diffuse[0] * 255.0) as u8,
+                                                     (diffuse[1] * 255.0) as u8,
+                                                     (diffuse[2] * 255.0) as u8,
+                                                     255]
+                                         ))
         } else {
             println!("No diffuse texture found: defaulting to placeholder texture");
-            diffuse_texture = generate_placeholder_texture(device,
-                                                           queue,
-                                                           128,
-                                                           128,
-                                                           true,
-                                                           image::Rgba([
-                                                                       0,
-                                                                       0,
-                                                                       0,
-                                                                       255
-                                                           ]));
-        }
+            generate_placeholder_texture(device,
+                                         queue,
+                                         128,
+                                         128,
+                                         true,
+                                         image::Rgba([
+                                                     0,
+                                                     0,
+                                                     0,
+                                                     255
+                                         ]))
+        };
         
-        if m.normal_texture.is_some(){
-            let (_, norm) = m.normal_texture.as_ref().unwrap().rsplit_once(" ").unwrap();
+        let normal_texture = if let Some(normal_path) = &m.normal_texture {
+            let norm = normal_texture_file(normal_path);
             println!("Loading normal texture: {}", norm);
-            normal_texture = load_texture(norm, subfolder, true, device, queue).await?;
+            load_texture(norm, subfolder, true, device, queue).await?
         } else {
             println!("No normal texture found: defaulting to placeholder texture");
             // has to match diffuse_texture
-            normal_texture = generate_placeholder_texture(device,
-                                                          queue,
-                                                          diffuse_texture.texture.width(),
-                                                          diffuse_texture.texture.height(),
-                                                          false, image::Rgba([
-                                                                             0,
-                                                                             0,
-                                                                             0,
-                                                                             255
-                                                          ]));
-        }
+            generate_placeholder_texture(device,
+                                         queue,
+                                         diffuse_texture.texture.width(),
+                                         diffuse_texture.texture.height(),
+                                         false, image::Rgba([
+                                                            0,
+                                                            0,
+                                                            0,
+                                                            255
+                                         ]))
+        };
 
         materials.push(model::Material::new (
             device,
             &m.name,
+            materials.len() as u32,
             diffuse_texture,
             normal_texture,
             layout,
@@ -153,6 +208,7 @@ pub async fn load_model_obj(
         materials.push(model::Material::new (
             device, 
             name,
+            0,
             generate_placeholder_texture(
                 device, 
                 queue, 
@@ -270,13 +326,30 @@ pub async fn load_model_obj(
                 contents: bytemuck::cast_slice(&m.mesh.indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
+            let wireframe_vertex_buffer = if device.features().contains(wgpu::Features::POLYGON_MODE_LINE) {
+                None
+            } else {
+                let unindexed = indices.iter()
+                    .map(|i| vertices[*i as usize])
+                    .collect::<Vec<_>>();
+                Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&format!("{:?} Wireframe Vertex Buffer", file_name)),
+                    contents: bytemuck::cast_slice(&unindexed),
+                    usage: wgpu::BufferUsages::VERTEX,
+                }))
+            };
 
             model::Mesh {
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
+                wireframe_vertex_buffer,
+                num_vertices: vertices.len() as u32,
                 num_elements: m.mesh.indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
+                aabb: Aabb::from_points(vertices.iter().map(|v| v.position.into())),
+                bounding_sphere: BoundingSphere::from_points(vertices.iter().map(|v| v.position.into())),
+                geometry: mesh_geometry(&m.mesh),
             }
         })
         .collect::<Vec<_>>();
@@ -284,3 +357,80 @@ pub async fn load_model_obj(
     Ok(model::Model { meshes, materials })
 }
 
+fn mesh_geometry(mesh: &tobj::Mesh) -> MeshGeometry {
+    MeshGeometry::new(
+        mesh.positions.chunks_exact(3).map(|p| cgmath::Point3::new(p[0], p[1], p[2])).collect(),
+        mesh.indices.clone(),
+        mesh.material_id.unwrap_or(0),
+    )
+}
+
+// The meshes load_model_obj would build, without a device or textures
+pub fn load_model_geometry(file_name: &str, subfolder: &str) -> anyhow::Result<Vec<MeshGeometry>> {
+    let obj = load_obj(file_name, subfolder)?;
+    Ok(obj.models.iter().map(|m| mesh_geometry(&m.mesh)).collect())
+}
+
+pub struct MeshInfo {
+    pub name: String,
+    pub vertices: usize,
+    pub triangles: usize,
+    pub material: Option<usize>,
+    pub has_normals: bool,
+    pub has_tex_coords: bool,
+}
+
+pub struct MaterialInfo {
+    pub name: String,
+    pub diffuse_texture: Option<String>,
+    pub normal_texture: Option<String>,
+}
+
+// What load_model_obj would load, without a device
+pub struct ModelInfo {
+    pub meshes: Vec<MeshInfo>,
+    pub materials: Vec<MaterialInfo>,
+    pub aabb: Option<Aabb>,
+    // material libraries and textures that are referenced but don't exist
+    pub missing_files: Vec<PathBuf>,
+}
+
+pub fn load_model_info(file_name: &str, subfolder: &str) -> anyhow::Result<ModelInfo> {
+    let ObjFile { models, materials: obj_materials, missing_libraries } = load_obj(file_name, subfolder)?;
+
+    // a missing material library is already in missing_files
+    let mut missing_files = missing_libraries;
+    let materials = obj_materials.unwrap_or_default()
+        .into_iter()
+        .map(|m| MaterialInfo {
+            name: m.name,
+            diffuse_texture: m.diffuse_texture,
+            normal_texture: m.normal_texture.as_deref().map(|path| normal_texture_file(path).to_string()),
+        })
+        .collect::<Vec<_>>();
+    for material in &materials {
+        for texture in material.diffuse_texture.iter().chain(&material.normal_texture) {
+            let texture_path = resource_path(texture, subfolder);
+            if !texture_path.exists() {
+                missing_files.push(texture_path);
+            }
+        }
+    }
+
+    let meshes = models.iter()
+        .map(|m| MeshInfo {
+            name: m.name.clone(),
+            vertices: m.mesh.positions.len() / 3,
+            triangles: m.mesh.indices.len() / 3,
+            material: m.mesh.material_id,
+            has_normals: !m.mesh.normals.is_empty(),
+            has_tex_coords: !m.mesh.texcoords.is_empty(),
+        })
+        .collect();
+    let aabb = models.iter()
+        .filter(|m| !m.mesh.positions.is_empty())
+        .map(|m| Aabb::from_points(m.mesh.positions.chunks_exact(3).map(|p| cgmath::Point3::new(p[0], p[1], p[2]))))
+        .reduce(|a, b| a.union(&b));
+
+    Ok(ModelInfo { meshes, materials, aabb, missing_files })
+}
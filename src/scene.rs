@@ -0,0 +1,20 @@
+use crate::instancing::Instance;
+use crate::model::Model;
+
+/// A model and the instances it is drawn at. The renderer shows one scene at
+/// a time, see `Renderer::set_scene`.
+pub struct Scene {
+    pub model: Model,
+    pub instances: Vec<Instance>,
+}
+
+impl Scene {
+    pub fn new(model: Model, instances: Vec<Instance>) -> Self {
+        Self { model, instances }
+    }
+
+    /// A single instance at the origin
+    pub fn single(model: Model) -> Self {
+        Self::new(model, vec![Instance::new(cgmath::Vector3::new(0.0, 0.0, 0.0))])
+    }
+}
@@ -0,0 +1,438 @@
+use crate::instancing::{index_after_insert, index_after_remove, InstanceRaw};
+use crate::model::{Model, ModelVertex, Vertex};
+use crate::viewport::PaneRect;
+
+// Outline colors by selection order, repeating after the last
+pub const SELECTION_COLORS: [[f32; 3]; 6] = [
+    [1.0, 0.5, 0.0],
+    [0.0, 0.8, 1.0],
+    [1.0, 0.2, 0.8],
+    [1.0, 0.9, 0.1],
+    [0.3, 1.0, 0.3],
+    [0.5, 0.4, 1.0],
+];
+
+const MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const OUTLINE_WIDTH: i32 = 2;
+const OVERLAY_ALPHA: f32 = 0.25;
+
+// Selected instances in the order they were selected, which picks their color
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+    instances: Vec<usize>,
+}
+
+impl Selection {
+    pub fn instances(&self) -> &[usize] {
+        &self.instances
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    pub fn contains(&self, instance: usize) -> bool {
+        self.instances.contains(&instance)
+    }
+
+    pub fn color(slot: usize) -> [f32; 3] {
+        SELECTION_COLORS[slot % SELECTION_COLORS.len()]
+    }
+
+    // Replaces the selection with instance
+    pub fn select(&mut self, instance: usize) {
+        self.instances = vec![instance];
+    }
+
+    // Adds instance, or removes it if it was selected. Returns whether it's
+    // selected now.
+    pub fn toggle(&mut self, instance: usize) -> bool {
+        match self.instances.iter().position(|&selected| selected == instance) {
+            Some(slot) => {
+                self.instances.remove(slot);
+                false
+            }
+            None => {
+                self.instances.push(instance);
+                true
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+    }
+
+    // Follows InstanceManager::remove: drops removed and renumbers the
+    // instance that took its index
+    pub fn removed(&mut self, removed: usize, last: usize) {
+        self.instances = self.instances.iter()
+            .filter_map(|&instance| index_after_remove(instance, removed, last))
+            .collect();
+    }
+
+    // Follows InstanceManager::insert, the inserted instance isn't selected
+    pub fn inserted(&mut self, inserted: usize, last: usize) {
+        for instance in &mut self.instances {
+            *instance = index_after_insert(*instance, inserted, last);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    width: i32,
+    overlay: f32,
+    _padding: [u32; 2],
+}
+
+// Outlines the selected instances on top of the shaded frame. They are drawn
+// into a color mask without depth testing, so hidden parts are outlined too,
+// then a full screen pass draws the mask's edges and the optional tint.
+pub struct SelectionRenderer {
+    mask_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+    color_layout: wgpu::BindGroupLayout,
+    color_buffer: wgpu::Buffer,
+    color_bind_group: wgpu::BindGroup,
+    // per selected instance, one vec4 color per dynamic offset
+    color_stride: u32,
+    color_capacity: usize,
+    outline_layout: wgpu::BindGroupLayout,
+    outline_buffer: wgpu::Buffer,
+    mask: wgpu::TextureView,
+    mask_size: (u32, u32),
+    outline_bind_group: wgpu::BindGroup,
+    pub overlay: bool,
+}
+
+impl SelectionRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let color_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Selection Color Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(16),
+                },
+                count: None,
+            }],
+        });
+        let outline_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Outline Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let mask_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Selection Mask Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("selection_mask.wgsl").into()),
+        });
+        let mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Selection Mask Pipeline"),
+            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Selection Mask Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &color_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: wgpu::VertexState {
+                module: &mask_shader,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &mask_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: MASK_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            // the silhouette includes back faces and doesn't care about depth
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let outline_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("outline.wgsl").into()),
+        });
+        let outline_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline Pipeline"),
+            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Outline Pipeline Layout"),
+                bind_group_layouts: &[&outline_layout],
+                push_constant_ranges: &[],
+            })),
+            vertex: wgpu::VertexState {
+                module: &outline_shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &outline_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let color_stride = 16u32.next_multiple_of(device.limits().min_uniform_buffer_offset_alignment);
+        let (color_buffer, color_bind_group) = Self::create_color_buffer(device, &color_layout, color_stride, 1);
+        let outline_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Buffer"),
+            size: std::mem::size_of::<OutlineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mask = Self::create_mask(device, 1, 1);
+        let outline_bind_group = Self::create_outline_bind_group(device, &outline_layout, &mask, &outline_buffer);
+        Self {
+            mask_pipeline,
+            outline_pipeline,
+            color_layout,
+            color_buffer,
+            color_bind_group,
+            color_stride,
+            color_capacity: 1,
+            outline_layout,
+            outline_buffer,
+            mask,
+            mask_size: (1, 1),
+            outline_bind_group,
+            overlay: false,
+        }
+    }
+
+    fn create_color_buffer(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        stride: u32,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Selection Color Buffer"),
+            size: stride as wgpu::BufferAddress * capacity as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Selection Color Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(16),
+                }),
+            }],
+        });
+        (buffer, bind_group)
+    }
+
+    fn create_mask(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Selection Mask"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: MASK_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        }).create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn create_outline_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        mask: &wgpu::TextureView,
+        outline_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Outline Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(mask),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: outline_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    // Call before draw, sizes the mask to the target and uploads the colors
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, selection: &Selection, target_size: (u32, u32)) {
+        let size = (target_size.0.max(1), target_size.1.max(1));
+        if size != self.mask_size {
+            self.mask = Self::create_mask(device, size.0, size.1);
+            self.mask_size = size;
+            self.outline_bind_group = Self::create_outline_bind_group(device, &self.outline_layout, &self.mask, &self.outline_buffer);
+        }
+        let selected = selection.instances().len();
+        if selected > self.color_capacity {
+            self.color_capacity = selected.next_power_of_two();
+            (self.color_buffer, self.color_bind_group) =
+                Self::create_color_buffer(device, &self.color_layout, self.color_stride, self.color_capacity);
+        }
+        let mut colors = vec![0u8; self.color_stride as usize * selected];
+        for (slot, bytes) in colors.chunks_exact_mut(self.color_stride as usize).enumerate() {
+            let [r, g, b] = Selection::color(slot);
+            bytes[..16].copy_from_slice(bytemuck::cast_slice(&[r, g, b, 1.0]));
+        }
+        queue.write_buffer(&self.color_buffer, 0, &colors);
+        let outline = OutlineUniform {
+            width: OUTLINE_WIDTH,
+            overlay: if self.overlay { OVERLAY_ALPHA } else { 0.0 },
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.outline_buffer, 0, bytemuck::bytes_of(&outline));
+    }
+
+    // Outlines the selection in each pane, as seen through that pane's camera
+    pub fn draw<'a>(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        panes: impl IntoIterator<Item = (PaneRect, &'a wgpu::BindGroup)> + Clone,
+        selection: &Selection,
+        model: &Model,
+        instance_buffer: &wgpu::Buffer,
+    ) {
+        if selection.is_empty() {
+            return;
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Selection Mask Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.mask,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            render_pass.set_pipeline(&self.mask_pipeline);
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            for (rect, camera_bind_group) in panes.clone() {
+                render_pass.set_viewport(rect.x as f32, rect.y as f32, rect.width as f32, rect.height as f32, 0.0, 1.0);
+                render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+                render_pass.set_bind_group(0, camera_bind_group, &[]);
+                for (slot, &instance) in selection.instances().iter().enumerate() {
+                    render_pass.set_bind_group(1, &self.color_bind_group, &[slot as u32 * self.color_stride]);
+                    let instance = instance as u32;
+                    for mesh in &model.meshes {
+                        render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                        render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                        render_pass.draw_indexed(0..mesh.num_elements, 0, instance..instance + 1);
+                    }
+                }
+            }
+        }
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.outline_pipeline);
+        render_pass.set_bind_group(0, &self.outline_bind_group, &[]);
+        // scissored per pane so outlines don't bleed into the neighbours
+        for (rect, _) in panes {
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection_keeps_order_and_follows_the_scene() {
+        let mut selection = Selection::default();
+        selection.select(3);
+        assert!(selection.toggle(5));
+        assert!(selection.toggle(1));
+        assert_eq!(selection.instances(), &[3, 5, 1]);
+        assert!(!selection.toggle(5));
+        assert_eq!(selection.instances(), &[3, 1]);
+        selection.select(0);
+        assert_eq!(selection.instances(), &[0]);
+        assert_eq!(Selection::color(SELECTION_COLORS.len()), Selection::color(0));
+    }
+
+    #[test]
+    fn selection_follows_removed_and_reinserted_instances() {
+        let mut selection = Selection::default();
+        selection.select(1);
+        selection.toggle(4);
+        selection.toggle(2);
+        // of five instances, removing 1 moves 4 into its slot
+        selection.removed(1, 4);
+        assert_eq!(selection.instances(), &[1, 2]);
+        // and undoing it moves that one back
+        selection.inserted(1, 4);
+        assert_eq!(selection.instances(), &[4, 2]);
+        // removing the last instance only drops it
+        selection.removed(4, 4);
+        assert_eq!(selection.instances(), &[2]);
+    }
+}
@@ -3,17 +3,18 @@ struct CameraUniform {
     view_proj: mat4x4<f32>,
 }
 
-struct TransformationUniform {
-    rot_mat: mat4x4<f32>,
-    scale_mat: mat4x4<f32>,
-}
-
 struct DataUniform {
     iFrame: u32,
     iTimeDelta: f32,
     iTime: f32,
+    debug_view: u32,
 }
 
+// matches DebugView in debug_view.rs
+const DEBUG_VIEW_NORMALS: u32 = 2u;
+const DEBUG_VIEW_UV_CHECKER: u32 = 4u;
+const DEBUG_VIEW_MATERIAL_ID: u32 = 5u;
+
 struct Light {
     position: vec3<f32>,
     color: vec3<f32>
@@ -23,12 +24,9 @@ struct Light {
 var<uniform> camera: CameraUniform;
 
 @group(2) @binding(0)
-var<uniform> transformation: TransformationUniform;
-
-@group(3) @binding(0)
 var<uniform> data: DataUniform;
 
-@group(4) @binding(0)
+@group(3) @binding(0)
 var<uniform> light: Light;
 
 struct VertexInput {
@@ -47,6 +45,8 @@ struct VertexOutput {
     @location(1) tangent_position: vec3<f32>,
     @location(2) tangent_light_position: vec3<f32>,
     @location(3) tangent_view_position: vec3<f32>,
+    @location(4) world_normal: vec3<f32>,
+    @location(5) tint: vec4<f32>,
 };
 
 struct InstanceInput {
@@ -57,6 +57,7 @@ struct InstanceInput {
     @location(9) normal_matrix_0: vec3<f32>,
     @location(10) normal_matrix_1: vec3<f32>,
     @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) tint: vec4<f32>,
 }
 
 @vertex 
@@ -75,16 +76,19 @@ fn vs_main(
         instance.normal_matrix_1,
         instance.normal_matrix_2,
     );
+    // normal_matrix is the inverse-transpose of the model matrix, tangents
+    // lie in the surface so they take the plain model matrix instead
+    let model_matrix_3 = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
     let world_normal = normalize(normal_matrix * model.normal);
-    let world_tangent = normalize(normal_matrix * model.tangent);
-    let world_bitangent = normalize(normal_matrix * model.bitangent);
+    let world_tangent = normalize(model_matrix_3 * model.tangent);
+    let world_bitangent = normalize(model_matrix_3 * model.bitangent);
     let tangent_matrix = transpose(mat3x3<f32>(
         world_tangent,
         world_bitangent,
         world_normal,
     ));
 
-    let world_position = model_matrix * transformation.scale_mat * transformation.rot_mat * vec4<f32>(model.position, 1.0);
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
 
     var out: VertexOutput;
     out.clip_position = camera.view_proj * world_position;
@@ -92,6 +96,8 @@ fn vs_main(
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+    out.world_normal = world_normal;
+    out.tint = instance.tint;
     return out;
 }
 
@@ -104,6 +110,30 @@ var t_normal: texture_2d<f32>;
 @group(0) @binding(3)
 var s_normal: sampler;
 
+struct MaterialUniform {
+    id: u32,
+}
+@group(0) @binding(4)
+var<uniform> material: MaterialUniform;
+
+fn hue_to_rgb(hue: f32) -> vec3<f32> {
+    let k = vec3<f32>(0.0, 2.0 / 3.0, 1.0 / 3.0);
+    return clamp(abs(fract(hue + k) * 6.0 - 3.0) - 1.0, vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn debug_color(in: VertexOutput) -> vec4<f32> {
+    if data.debug_view == DEBUG_VIEW_NORMALS {
+        return vec4<f32>(normalize(in.world_normal) * 0.5 + 0.5, 1.0);
+    }
+    if data.debug_view == DEBUG_VIEW_UV_CHECKER {
+        let cell = floor(in.tex_coords * 8.0);
+        let checker = select(0.35, 1.0, (i32(cell.x) + i32(cell.y)) % 2 == 0);
+        return vec4<f32>(vec3<f32>(fract(in.tex_coords), 0.0) * 0.5 + checker * 0.5, 1.0);
+    }
+    // golden ratio hue steps keep neighbouring ids visually distinct
+    return vec4<f32>(hue_to_rgb(fract(f32(material.id) * 0.618034)), 1.0);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     //return textureSample(t_diffuse, s_diffuse, in.tex_coords);
@@ -112,7 +142,13 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     //                   0.5 + 0.5 * cos(data.iTime + in.tex_coords.x + 4.0),
     //                   1.0,
     //              );
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    if data.debug_view == DEBUG_VIEW_NORMALS
+        || data.debug_view == DEBUG_VIEW_UV_CHECKER
+        || data.debug_view == DEBUG_VIEW_MATERIAL_ID {
+        return debug_color(in);
+    }
+
+    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.tint;
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
     
     let ambient_strength = 0.1;
@@ -1,5 +1,5 @@
 use wgpu::util::DeviceExt;
-use winit::{window::Window, event::*};
+use winit::{window::{Window, WindowId}, event::*};
 use cgmath::prelude::*;
 
 use crate::texture;
@@ -7,8 +7,100 @@ use crate::texture::Texture;
 use crate::camera::*;
 use crate::transformation::*;
 use crate::instancing::*;
-use crate::model::{ModelVertex, Vertex, DrawModel, Model};
+use crate::model::{ModelVertex, Vertex, DrawModel, Model, TextureSlot};
 use crate::resources::*;
+use crate::debug_view::{DebugView, DebugViewRenderer};
+use crate::debug_draw::DebugDraw;
+use crate::gizmo::{Gizmo, GizmoMode, GizmoSpace, Handle};
+use crate::history::{Edit, History};
+use crate::input_map::{Action, Input, InputMap, INPUT_MAP_FILE};
+use crate::culling::{CullMode, Frustum, cull_model};
+use crate::picking::{decode_pick_id, Pick, Picker};
+use crate::raycast::{Hit, Ray};
+use crate::selection::{Selection, SelectionRenderer};
+use crate::gpu_culling::GpuCuller;
+use crate::stats::Stats;
+use crate::bounds::Aabb;
+use crate::camera_path::{CameraKeyframe, CameraPath};
+use crate::capture::{CaptureSettings, FrameCapture, RenderJob, Screenshot};
+use crate::scene::Scene;
+use crate::render_target::{RenderTarget, TextureTarget, WindowTarget};
+use crate::viewport::{match_depth_mode, PaneRect, SplitLayout, ViewWindow, Viewport};
+use crate::frame_limiter::FrameLimiter;
+use crate::timestep::{FixedTimestep, SIMULATION_HZ};
+
+const CAMERA_PATH_FILE: &str = "camera_path.txt";
+// how far the cursor can move between press and release and still pick
+const CLICK_SLOP: f64 = 4.0;
+// Length of the gizmo's axes as a fraction of the pane's half height
+const GIZMO_SCREEN_SIZE: f32 = 0.3;
+
+fn create_instance(options: &RendererOptions) -> wgpu::Instance {
+    wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: options.backends,
+        dx12_shader_compiler: Default::default(),
+    })
+}
+
+async fn request_adapter(instance: &wgpu::Instance, surface: Option<&wgpu::Surface>) -> wgpu::Adapter {
+    let adapter = instance.request_adapter(
+        &wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: surface,
+            force_fallback_adapter: false,
+        },
+    ).await.expect("no graphics adapter for the requested backend");
+    println!("Using {:?} on {}", adapter.get_info().backend, adapter.get_info().name);
+    adapter
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            // wireframe debug view uses PolygonMode::Line when it can
+            features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
+            limits: 
+                wgpu::Limits {
+                    max_bind_groups: 8,
+                    ..Default::default()
+                },
+            label: None,
+        },
+        None,
+    ).await.unwrap()
+}
+
+fn begin_scene_pass<'a>(
+    encoder: &'a mut wgpu::CommandEncoder,
+    view: &'a wgpu::TextureView,
+    depth_view: &'a wgpu::TextureView,
+    depth_mode: DepthMode,
+) -> wgpu::RenderPass<'a> {
+    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Render Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color {
+                    r: 0.5,
+                    g: 0.5,
+                    b: 0.5,
+                    a: 1.0,
+                }),
+                store: true,
+            },
+        })],
+        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+            view: depth_view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(depth_mode.clear_value()),
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    })
+}
 
 fn create_render_pipeline(
     device: &wgpu::Device,
@@ -17,6 +109,7 @@ fn create_render_pipeline(
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
+    depth_mode: DepthMode,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(shader);
 
@@ -55,7 +148,7 @@ fn create_render_pipeline(
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
             depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_compare: depth_mode.compare(),
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
@@ -68,6 +161,40 @@ fn create_render_pipeline(
     })
 }
 
+// Holds the per-mesh instance lists produced by culling, rewritten every frame
+fn create_culled_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Culled Instance Buffer"),
+        size: (capacity.max(1) * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// How the renderer picks its GPU and sets up input
+#[derive(Clone, Debug)]
+pub struct RendererOptions {
+    /// Graphics APIs to pick the adapter from
+    pub backends: wgpu::Backends,
+    /// Waits for vblank (Fifo) when presenting, otherwise presents immediately
+    pub vsync: bool,
+    /// Input map loaded over the default bindings, input_map.txt when it exists
+    pub bindings: Option<std::path::PathBuf>,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        Self { backends: wgpu::Backends::all(), vsync: true, bindings: None }
+    }
+}
+
+// The light as it can be edited, the uniform follows it
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Light {
+    pub position: cgmath::Vector3<f32>,
+    pub color: [f32; 3],
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
@@ -85,6 +212,13 @@ pub struct DataUniform {
     pub frame: u32,
     pub delta_time: f32,
     pub time: f32,
+    pub debug_view: u32,
+}
+
+impl Default for DataUniform {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DataUniform {
@@ -93,95 +227,158 @@ impl DataUniform {
             frame: 0,
             delta_time: 0.0,
             time: 0.0,
+            debug_view: DebugView::Shaded as u32,
         }
     }
-    pub fn update(&mut self, delta_time: f32) {
+    // time is the interpolated simulation time, not accumulated frame times
+    pub fn update(&mut self, delta_time: f32, time: f32) {
         self.frame += 1;
         self.delta_time = delta_time;
-        self.time += self.delta_time;
+        self.time = time;
     }
 }
 
-pub struct State {
-    pub surface: wgpu::Surface,
+/// Owns the GPU resources and the scene, and draws a frame per `update` and
+/// `render` into its `RenderTarget`. Fields are public for tools that need to
+/// reach past the methods.
+pub struct Renderer {
+    pub target: Box<dyn RenderTarget>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
-    pub config: wgpu::SurfaceConfiguration,
-    pub size: winit::dpi::PhysicalSize<u32>,
-    pub window: Window,
+    // cursor position in physical pixels
     pub pos: (f64, f64),
-    pub render_pipelines: Vec<wgpu::RenderPipeline>,
+    // indexed by pipeline, then DepthMode
+    pub render_pipelines: Vec<[wgpu::RenderPipeline; 2]>,
+    // simulated camera and the one from the step before, render_camera is
+    // interpolated between them
     pub camera: Camera,
+    pub previous_camera: Camera,
+    pub render_camera: Camera,
+    pub camera_clock: FixedTimestep,
+    pub simulation: FixedTimestep,
     pub camera_uniform: CameraUniform,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
     pub camera_controller: CameraController,
-    pub rotation: RotationDeg,
-    pub transformation_uniform: TransformationUniform,
-    pub transformation_buffer: wgpu::Buffer,
-    pub transformation_bind_group: wgpu::BindGroup,
+    pub orbit_controller: OrbitController,
+    pub camera_mode: CameraMode,
+    pub camera_path: CameraPath,
+    // DataUniform::time the path's keyframe times are relative to
+    pub path_recording_start: Option<f32>,
+    pub path_playback_start: Option<f32>,
+    pub capture: Option<FrameCapture>,
+    pub frame_limiter: FrameLimiter,
+    pub screenshot_requested: bool,
+    // screenshots still being read back, finished ones are dropped
+    pub screenshots: Vec<Screenshot>,
     pub rotation_controller: RotationController,
-    pub instances: Vec<Instance>,
-    pub instance_buffer: wgpu::Buffer,
+    // the instance RotationController acts on, cycled with Tab
+    pub selected_instance: usize,
+    pub instances: InstanceManager,
     pub data_uniform: DataUniform,
     pub data_buffer: wgpu::Buffer,
     pub data_bind_group: wgpu::BindGroup,
     pub depth_texture: Texture,
     pub obj_model: Model,
     pub light_uniform: LightUniform,
+    pub light_position: cgmath::Vector3<f32>,
+    pub previous_light_position: cgmath::Vector3<f32>,
     pub light_buffer: wgpu::Buffer,
     pub light_bind_group: wgpu::BindGroup,
     pub projection: Projection,
     pub mouse_pressed: bool,
+    // where the left button went down, a release close by is a click
+    pub click_start: Option<(f64, f64)>,
+    pub picker: Picker,
+    // drawn into the next frame's pick pass
+    pub pick_requested: Option<(u32, u32)>,
+    // the last click's result
+    pub picked: Option<Pick>,
+    // shift was held for the pending pick, which then adds to the selection
+    pub pick_extends_selection: bool,
+    // outlined on screen
+    pub selection: Selection,
+    pub selection_renderer: SelectionRenderer,
+    pub modifiers: ModifiersState,
+    pub debug_view: DebugView,
+    pub debug_view_renderer: DebugViewRenderer,
+    pub debug: DebugDraw,
+    pub show_gizmos: bool,
+    // history summary in the window title
+    pub show_history: bool,
+    // transform handles on the selected instance, drawn over the scene
+    pub gizmo: Gizmo,
+    pub gizmo_draw: DebugDraw,
+    // undo and redo for scene edits
+    pub history: History,
+    pub cull_mode: CullMode,
+    pub gpu_culler: GpuCuller,
+    pub culled_instance_buffer: wgpu::Buffer,
+    pub culled_instance_capacity: usize,
+    pub mesh_instances: Vec<std::ops::Range<u32>>,
+    pub stats: Stats,
+    pub texture_bind_group_layout: wgpu::BindGroupLayout,
+    // the scene that isn't showing, swapped in and out by toggle_stress_test
+    pub stashed_scene: Option<Scene>,
+    pub stress_test: bool,
+    pub camera_bind_group_layout: wgpu::BindGroupLayout,
+    // None when the caller brought its own device, then windows can't be opened
+    pub instance: Option<wgpu::Instance>,
+    pub adapter: Option<wgpu::Adapter>,
+    // split screen, panes holds the cameras of every pane but the first
+    pub split_layout: SplitLayout,
+    pub panes: Vec<Viewport>,
+    // the pane under the cursor, which gets camera input
+    pub active_pane: usize,
+    pub maximized: bool,
+    // extra windows onto the same scene, see open_window
+    pub windows: Vec<ViewWindow>,
+    // what keys and buttons do, reloaded from input_map_path by app.reload_bindings
+    pub input_map: InputMap,
+    pub input_map_path: std::path::PathBuf,
+    // None while the main window has focus
+    pub focused_window: Option<WindowId>,
 }
 
-impl State {
-    pub async fn new(window: Window) -> Self {
-        let size = window.inner_size();
-
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
-
+impl Renderer {
+    /// Creates the surface for window and loads model as the initial scene
+    pub async fn new(window: Window, model: &ModelPath, options: &RendererOptions) -> Self {
+        let instance = create_instance(options);
         let surface = unsafe { instance.create_surface(&window) }.unwrap();
-
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            },
-        ).await.unwrap();
-
-        let (device, queue) = adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                features: wgpu::Features::empty(),
-                limits: 
-                    wgpu::Limits {
-                        max_bind_groups: 8,
-                        ..Default::default()
-                    },
-                label: None,
-            },
-            None,
-        ).await.unwrap();
-
-        let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps.formats.iter()
-            .copied()
-            .find(|format| format.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
-        };
-        surface.configure(&device, &config);
+        let adapter = request_adapter(&instance, Some(&surface)).await;
+        let (device, queue) = request_device(&adapter).await;
+        let target = WindowTarget::new(window, surface, &adapter, &device, options.vsync, None);
+        let mut renderer = Self::with_target(device, queue, Box::new(target), model).await;
+        // kept for the surfaces of extra windows
+        renderer.instance = Some(instance);
+        renderer.adapter = Some(adapter);
+        // input_map.txt is optional, a file asked for on the command line isn't
+        if let Some(path) = &options.bindings {
+            renderer.input_map_path = path.clone();
+        }
+        if options.bindings.is_some() || renderer.input_map_path.exists() {
+            renderer.reload_input_map();
+        }
+        renderer
+    }
+    /// Renders into an offscreen texture, no window or display needed
+    pub async fn headless(width: u32, height: u32, model: &ModelPath, options: &RendererOptions) -> Self {
+        let instance = create_instance(options);
+        let adapter = request_adapter(&instance, None).await;
+        let (device, queue) = request_device(&adapter).await;
+        let target = TextureTarget::new(&device, width, height, wgpu::TextureFormat::Rgba8UnormSrgb);
+        Self::with_target(device, queue, Box::new(target), model).await
+    }
+    /// For apps that bring their own device and target, e.g. a ViewTarget
+    /// around a texture of theirs. The device needs max_bind_groups >= 8.
+    pub async fn with_target(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        target: Box<dyn RenderTarget>,
+        model: &ModelPath,
+    ) -> Self {
+        let (width, height) = target.size();
+        let format = target.format();
 
         let texture_bind_group_layout = 
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -218,6 +415,16 @@ impl State {
                         ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("texture_bind_group_layout"),
             });
@@ -225,7 +432,8 @@ impl State {
         let pos = (0.0, 0.0);
 
         let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection = Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
+        let mut projection = Projection::new(width, height, cgmath::Deg(45.0), 0.1, 100.0);
+        projection.set_depth_mode(DepthMode::ReverseZ);
         let camera_controller = CameraController::new(4.0, 1.0);
 
         let mut camera_uniform = CameraUniform::new();
@@ -270,52 +478,8 @@ impl State {
             }
         );
 
-        let rotation: RotationDeg = RotationDeg::new();
-        let mut transformation_uniform = TransformationUniform::new();
-        transformation_uniform.update_rotation(&rotation);
-        transformation_uniform.update_scaling(1.0);
-
-        let transformation_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Rotation Buffer"),
-                contents: bytemuck::cast_slice(&[transformation_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            }
-        );
-
-        let transformation_bind_group_layout = device.create_bind_group_layout(
-            &wgpu::BindGroupLayoutDescriptor {
-                label: Some("Rotation Buffer Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }
-                ],
-            }
-        );
-
-        let transformation_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &transformation_bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: transformation_buffer.as_entire_binding(),
-                    }
-                ],
-                label: Some("Transformation Bind Group"),
-            }
-        );
-
         let mut data_uniform = DataUniform::new();
-        data_uniform.update(0.0);
+        data_uniform.update(0.0, 0.0);
 
         let data_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
@@ -405,7 +569,6 @@ impl State {
                 bind_group_layouts: &[
                     &texture_bind_group_layout,
                     &camera_bind_group_layout,
-                    &transformation_bind_group_layout,
                     &data_bind_group_layout,
                     &light_bind_group_layout,
                 ],
@@ -413,7 +576,8 @@ impl State {
             }
         );
 
-        let render_pipeline = {
+        // one per DepthMode
+        let render_pipeline = DepthMode::ALL.map(|depth_mode| {
             let shader = wgpu::ShaderModuleDescriptor {
                 label: Some("Normal Shader"),
                 source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
@@ -421,76 +585,64 @@ impl State {
             create_render_pipeline(
                 &device,
                 &render_pipeline_layout,
-                config.format,
+                format,
                 Some(texture::Texture::DEPTH_FORMAT),
                 &[
                     ModelVertex::desc(),
                     InstanceRaw::desc(),
                 ],
                 shader,
+                depth_mode,
             )
-        };
+        });
         let light_render_pipeline = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Light Pipeline Layout"),
                 bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
                 push_constant_ranges: &[],
             });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[ModelVertex::desc(), InstanceRaw::desc()],
-                shader,
-            )
+            DepthMode::ALL.map(|depth_mode| {
+                let shader = wgpu::ShaderModuleDescriptor {
+                    label: Some("Light Shader"),
+                    source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
+                };
+                create_render_pipeline(
+                    &device,
+                    &layout,
+                    format,
+                    Some(texture::Texture::DEPTH_FORMAT),
+                    &[ModelVertex::desc(), InstanceRaw::desc()],
+                    shader,
+                    depth_mode,
+                )
+            })
         };
         let render_pipelines = vec![
             render_pipeline,
             light_render_pipeline,
         ];
+        let debug_view_renderer = DebugViewRenderer::new(&device, format, &camera_bind_group_layout);
+        let debug = DebugDraw::new(&device, format, &camera_bind_group_layout);
+        let gizmo_draw = DebugDraw::on_top(&device, format, &camera_bind_group_layout);
+        let picker = Picker::new(&device, &camera_bind_group_layout);
+        let selection_renderer = SelectionRenderer::new(&device, format, &camera_bind_group_layout);
         let rotation_controller = RotationController::new(100.0);
 
-        const SPACE_BETWEEN: f32 = 3.0;
-        let instances = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
-            (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                let position = cgmath::Vector3 { x, y: 0.0, z };
-
-                let rotation = if position.is_zero() {
-                    cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_z(), cgmath::Deg(0.0))
-                } else {
-                    cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                };
+        let mut instance = Instance::new(cgmath::Vector3::new(0.0, 0.0, 0.0));
+        instance.scale = cgmath::Vector3::new(0.5, 0.5, 0.5);
+        let instances = InstanceManager::new(&device, vec![instance]);
 
-                Instance {
-                    position, rotation,
-                }
-            })
-        }).collect::<Vec<_>>();
+        let culled_instance_capacity = instances.len() * 4;
+        let culled_instance_buffer = create_culled_instance_buffer(&device, culled_instance_capacity);
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Instance Buffer"),
-                contents: bytemuck::cast_slice(&instance_data),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
-        );
-
-        let depth_texture = texture::Texture::create_depth_texture(&device, &config, "depth_texture");
-        let obj_model = load_model_obj("raphtalia.obj", 
-                                   "raphtalia",
+        let depth_texture = texture::Texture::create_depth_texture(&device, (width, height), "depth_texture");
+        let obj_model = load_model_obj(&model.file_name,
+                                   &model.subfolder,
                                    &device,
                                    &queue,
                                    &texture_bind_group_layout
                         ).await.unwrap();
+        let gpu_culler = GpuCuller::new(&device, &obj_model, instances.buffer(), instances.len() as u32);
         /*let obj_model = load_model_fbx("Agnes.fbx", 
                                    "agnes",
                                    &device,
@@ -499,175 +651,1337 @@ impl State {
                         ).await.unwrap();*/
 
         Self {
-            surface,
+            target,
             device,
             queue,
-            config,
-            size,
-            window,
             pos,
             render_pipelines,
+            previous_camera: camera.clone(),
+            render_camera: camera.clone(),
             camera,
+            camera_clock: FixedTimestep::new(SIMULATION_HZ),
+            simulation: FixedTimestep::new(SIMULATION_HZ),
             camera_uniform,
             camera_buffer,
             camera_bind_group,
             camera_controller,
-            rotation,
-            transformation_uniform,
-            transformation_buffer,
-            transformation_bind_group,
+            orbit_controller: OrbitController::new(1.0),
+            camera_mode: CameraMode::Fly,
+            camera_path: CameraPath::new(),
+            path_recording_start: None,
+            path_playback_start: None,
+            capture: None,
+            frame_limiter: FrameLimiter::new(Some(144.0)),
+            screenshot_requested: false,
+            screenshots: Vec::new(),
             rotation_controller,
+            selected_instance: 0,
             instances,
-            instance_buffer,
             data_uniform,
             data_buffer,
             data_bind_group,
             depth_texture,
             obj_model,
+            light_position: light_uniform.position.into(),
+            previous_light_position: light_uniform.position.into(),
             light_uniform,
             light_buffer,
             light_bind_group,
             projection,
             mouse_pressed: false,
+            click_start: None,
+            picker,
+            pick_requested: None,
+            picked: None,
+            pick_extends_selection: false,
+            selection: Selection::default(),
+            selection_renderer,
+            modifiers: ModifiersState::empty(),
+            debug_view: DebugView::Shaded,
+            debug_view_renderer,
+            debug,
+            show_gizmos: false,
+            show_history: false,
+            gizmo: Gizmo::new(),
+            gizmo_draw,
+            history: History::new(),
+            cull_mode: CullMode::Cpu,
+            gpu_culler,
+            culled_instance_buffer,
+            culled_instance_capacity,
+            mesh_instances: Vec::new(),
+            stats: Stats::new(),
+            texture_bind_group_layout,
+            stashed_scene: None,
+            stress_test: false,
+            camera_bind_group_layout,
+            instance: None,
+            adapter: None,
+            split_layout: SplitLayout::Single,
+            panes: Vec::new(),
+            active_pane: 0,
+            maximized: false,
+            windows: Vec::new(),
+            input_map: InputMap::default(),
+            input_map_path: INPUT_MAP_FILE.into(),
+            focused_window: None,
         }
     }
-    pub fn window(&self) -> &Window {
-        &self.window
+    /// The window of a window target
+    pub fn window(&self) -> Option<&Window> {
+        self.target.window()
     }
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        self.projection.resize(new_size.width, new_size.height);
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
-            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+    /// Call on WindowEvent::Resized, zero sizes are ignored
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.target.resize(&self.device, width, height);
+        self.fit_to_target();
+    }
+    // View targets change size on their own, so this also runs every frame
+    fn fit_to_target(&mut self) {
+        let (width, height) = self.target.size();
+        let depth_size = self.depth_texture.texture.size();
+        if width == 0 || height == 0 || (width, height) == (depth_size.width, depth_size.height) {
+            return;
         }
+        self.depth_texture = texture::Texture::create_depth_texture(&self.device, (width, height), "depth_texture");
+        self.fit_panes();
+    }
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        println!("Debug view: {:?}", view);
+        if view == DebugView::MaterialId {
+            for mesh in &self.obj_model.meshes {
+                println!("  {} -> material {} ({})",
+                         mesh.name,
+                         self.obj_model.materials[mesh.material].id,
+                         self.obj_model.materials[mesh.material].name);
+            }
+        }
+        self.debug_view = view;
+        self.data_uniform.debug_view = view as u32;
+    }
+    /// Actions bound to a key or button event, and whether it was a press.
+    /// Releases end every action on the key, whatever modifiers are held.
+    pub fn event_actions(&self, event: &WindowEvent) -> (Vec<Action>, bool) {
+        self.actions_with(event, self.modifiers)
     }
+    /// Like event_actions, for an event to one of the extra windows
+    pub fn window_event_actions(&self, id: WindowId, event: &WindowEvent) -> (Vec<Action>, bool) {
+        let modifiers = self.windows.iter()
+            .find(|window| window.id() == id)
+            .map_or(ModifiersState::empty(), |window| window.modifiers);
+        self.actions_with(event, modifiers)
+    }
+    fn actions_with(&self, event: &WindowEvent, modifiers: ModifiersState) -> (Vec<Action>, bool) {
+        let (input, state) = match event {
+            WindowEvent::KeyboardInput { input: KeyboardInput { virtual_keycode: Some(key), state, .. }, .. } => (Input::Key(*key), *state),
+            WindowEvent::MouseInput { button, state, .. } => (Input::Mouse(*button), *state),
+            _ => return (Vec::new(), false),
+        };
+        match state {
+            ElementState::Pressed => (self.input_map.pressed(input, modifiers), true),
+            ElementState::Released => (self.input_map.released(input), false),
+        }
+    }
+    /// Returns true if the event was used, e.g. by a hotkey or the camera
     pub fn input(&mut self, event: &WindowEvent) -> bool {
-        /*if let WindowEvent::CursorMoved { position, .. } = event {
+        if let WindowEvent::Focused(true) = event {
+            self.focused_window = None;
+        }
+        if let WindowEvent::ModifiersChanged(modifiers) = event {
+            self.modifiers = *modifiers;
+        }
+        let (actions, pressed) = self.event_actions(event);
+        if pressed && actions.iter().any(|&action| self.run_action(action)) {
+            return true;
+        }
+        if let WindowEvent::CursorMoved { position, .. } = event {
             self.pos = (*position).into();
+            // the pane under the cursor gets camera input, but not mid-drag
+            let dragging = self.mouse_pressed || self.gizmo.is_dragging() || self.panes.iter().any(Viewport::is_dragging);
+            if !dragging && !self.maximized {
+                if let Some((pane, _)) = self.pane_rects().into_iter().find(|(_, rect)| rect.contains(self.pos.0, self.pos.1)) {
+                    self.active_pane = pane;
+                }
+            }
+            if self.gizmo.is_dragging() {
+                // shift snaps to the gizmo's increments
+                let moved = self.cursor_ray().and_then(|ray| self.gizmo.drag(&ray, self.modifiers.shift()));
+                if let Some((index, instance)) = moved {
+                    self.instances.place(index, instance);
+                }
+                return true;
+            }
+            self.gizmo.hovered = self.gizmo_hit();
+        }
+        if actions.contains(&Action::Select) {
+            // clicks pick, drags are left to the camera unless they start on the gizmo
+            if pressed {
+                if self.begin_gizmo_drag() {
+                    return true;
+                }
+                self.click_start = Some(self.pos);
+            } else {
+                if self.gizmo.is_dragging() {
+                    // a whole drag undoes as one edit
+                    if let Some(edit) = self.gizmo.end_drag(self.instances.instances()) {
+                        self.history.record(Edit::Transform { instance: edit.instance, before: edit.before, after: edit.after });
+                    }
+                    return true;
+                }
+                let moved = self.click_start.take()
+                    .map(|(x, y)| (x - self.pos.0).hypot(y - self.pos.1));
+                if moved.is_some_and(|moved| moved <= CLICK_SLOP) {
+                    self.pick_extends_selection = self.modifiers.shift();
+                    self.request_pick(self.pos.0 as u32, self.pos.1 as u32);
+                }
+            }
+        }
+        if !pressed {
+            // the active pane can change while a key is held
+            for &action in &actions {
+                self.camera_controller.process_action(action, false);
+                for pane in &mut self.panes {
+                    pane.controller.process_action(action, false);
+                }
+            }
+        }
+        if let WindowEvent::MouseWheel { delta, .. } = event {
+            if self.active_pane > 0 {
+                self.panes[self.active_pane - 1].process_scroll(delta);
+                return true;
+            }
+            match (self.camera_mode, self.projection.mode()) {
+                // moving the camera doesn't change anything in ortho, so zoom the extent
+                (CameraMode::Fly, ProjectionMode::Orthographic) => self.projection.zoom(delta),
+                (CameraMode::Fly, ProjectionMode::Perspective) => self.camera_controller.process_scroll(delta),
+                (CameraMode::Orbit, _) => self.orbit_controller.process_scroll(delta),
+            }
             return true;
-        }*/
-        (match event {
-            WindowEvent::KeyboardInput {
-                input:
-                    KeyboardInput {
-                        virtual_keycode: Some(key),
-                        state,
-                        ..
-                    },
-                ..
-            } => self.camera_controller.process_keyboard(*key, *state),
-            WindowEvent::MouseWheel { delta, .. } => {
-                self.camera_controller.process_scroll(delta);
+        }
+        let mut used = false;
+        for action in actions {
+            used |= self.rotation_controller.process_action(action, pressed);
+            used |= if self.active_pane > 0 {
+                self.panes[self.active_pane - 1].process_action(action, pressed)
+            } else {
+                if action == Action::CameraLook {
+                    self.mouse_pressed = pressed;
+                }
+                // fly keys are left alone while orbiting, so nothing is
+                // still held when switching back
+                let flying = self.camera_mode == CameraMode::Fly && self.camera_controller.process_action(action, pressed);
+                flying | self.orbit_controller.process_action(action, pressed)
+            };
+        }
+        used
+    }
+    /// Replaces the input map with the defaults and input_map_path on top.
+    /// Conflicting bindings are reported but the map is used anyway.
+    pub fn reload_input_map(&mut self) {
+        match InputMap::load(&self.input_map_path) {
+            Ok(map) => {
+                println!("Loaded input map {}", self.input_map_path.display());
+                for conflict in map.conflicts() {
+                    println!("  conflict: {}", conflict);
+                }
+                self.input_map = map;
+            }
+            Err(e) => println!("Failed to load input map: {:?}", e),
+        }
+    }
+    // Actions that fire once on press, returns false for held ones and the
+    // ones the event loop handles
+    fn run_action(&mut self, action: Action) -> bool {
+        if let Some(view) = DebugView::from_action(action) {
+            self.set_debug_view(view);
+            return true;
+        }
+        if let Some(mode) = GizmoMode::from_action(action) {
+            self.set_gizmo_mode(mode);
+            return true;
+        }
+        if let Some(preset) = ViewPreset::from_action(action) {
+            self.set_camera_mode(CameraMode::Orbit);
+            self.orbit_controller.set_view(&mut self.camera, preset);
+            return true;
+        }
+        match action {
+            Action::Undo => {
+                self.undo();
+            }
+            Action::Redo => {
+                self.redo();
+            }
+            Action::History => {
+                self.show_history = !self.show_history;
+                if self.show_history {
+                    println!("History:");
+                    for line in self.history.panel() {
+                        println!("{}", line);
+                    }
+                }
+            }
+            Action::SelectionOverlay => {
+                self.selection_renderer.overlay = !self.selection_renderer.overlay;
+                println!("Selection overlay: {}", self.selection_renderer.overlay);
+            }
+            Action::GizmoSpace => {
+                self.gizmo.space = match self.gizmo.space {
+                    GizmoSpace::World => GizmoSpace::Local,
+                    GizmoSpace::Local => GizmoSpace::World,
+                };
+                println!("Gizmo space: {:?}", self.gizmo.space);
+            }
+            Action::DebugHelpers => self.show_gizmos = !self.show_gizmos,
+            Action::LayoutNext => self.set_split_layout(self.split_layout.next()),
+            Action::LayoutMaximize if self.split_layout != SplitLayout::Single => {
+                self.maximized = !self.maximized;
+                self.fit_panes();
+                println!("Pane {} maximized: {}", self.active_pane, self.maximized);
+            }
+            Action::SelectNext if !self.instances.is_empty() => {
+                self.selected_instance = (self.selected_instance + 1) % self.instances.len();
+                self.selection.select(self.selected_instance);
+                println!("Selected instance {}", self.selected_instance);
+            }
+            Action::StressTest => self.toggle_stress_test(),
+            Action::AddInstance => {
+                let spacing = (self.obj_model.aabb().max - self.obj_model.aabb().min).magnitude();
+                let mut instance = Instance::new(cgmath::Vector3::new(spacing * self.instances.len() as f32, 0.0, 0.0));
+                instance.scale = cgmath::Vector3::new(0.5, 0.5, 0.5);
+                self.add_instance(instance);
+            }
+            Action::RemoveInstance if !self.instances.is_empty() => {
+                self.remove_instance(self.instances.len() - 1);
+            }
+            Action::CameraToggleMode => {
+                let mode = match self.camera_mode {
+                    CameraMode::Fly => CameraMode::Orbit,
+                    CameraMode::Orbit => CameraMode::Fly,
+                };
+                self.set_camera_mode(mode);
+            }
+            Action::CameraToggleProjection => {
+                let mode = match self.projection.mode() {
+                    ProjectionMode::Perspective => ProjectionMode::Orthographic,
+                    ProjectionMode::Orthographic => ProjectionMode::Perspective,
+                };
+                self.projection.set_mode(mode, self.focus_distance());
+                println!("Projection: {:?}", mode);
+            }
+            Action::CameraFrameScene => self.frame_scene(),
+            Action::DepthMode => {
+                let depth_mode = self.projection.depth_mode().next();
+                self.set_depth_mode(depth_mode);
+                println!("Depth: {:?}", depth_mode);
+            }
+            Action::PresentMode => self.next_present_mode(),
+            Action::FrameCap => {
+                self.frame_limiter.next_cap();
+                println!("Frame rate cap: {:?}", self.frame_limiter.fps());
+            }
+            Action::SimPause => {
+                self.simulation.toggle_pause();
+                println!("Simulation paused: {}", self.simulation.paused);
+            }
+            Action::SimStep => self.simulation.single_step(),
+            Action::SimSlower | Action::SimFaster => {
+                let factor = if action == Action::SimSlower { 0.5 } else { 2.0 };
+                self.simulation.time_scale = (self.simulation.time_scale * factor).clamp(1.0 / 64.0, 64.0);
+                println!("Time scale: {}", self.simulation.time_scale);
+            }
+            Action::Screenshot => self.screenshot_requested = true,
+            Action::Record => {
+                if self.capture.is_some() {
+                    self.stop_capture();
+                } else {
+                    self.start_capture(CaptureSettings {
+                        // e.g. CAPTURE_ENCODER="ffmpeg -y -f rawvideo -pix_fmt rgba -s {width}x{height} -r {fps} -i - capture.mp4"
+                        encoder: std::env::var("CAPTURE_ENCODER").ok()
+                            .map(|command| command.split_whitespace().map(String::from).collect()),
+                        ..Default::default()
+                    });
+                }
+            }
+            Action::CullMode => {
+                self.cull_mode = self.cull_mode.next();
+                println!("Frustum culling: {:?}", self.cull_mode);
+            }
+            Action::ReloadBindings => self.reload_input_map(),
+            _ => return self.camera_path_action(action),
+        }
+        true
+    }
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        if let Some(id) = self.focused_window {
+            if let Some(window) = self.windows.iter_mut().find(|window| window.id() == id) {
+                window.viewport.process_mouse_motion(dx, dy);
+            }
+            return;
+        }
+        if self.active_pane > 0 {
+            self.panes[self.active_pane - 1].process_mouse_motion(dx, dy);
+            return;
+        }
+        match self.camera_mode {
+            CameraMode::Fly if self.mouse_pressed => self.camera_controller.process_mouse(dx, dy),
+            CameraMode::Fly => {}
+            CameraMode::Orbit => self.orbit_controller.process_mouse(dx, dy),
+        }
+    }
+    /// Closest hit of a world space ray against every instance, and the
+    /// instance it hit. Runs on the CPU, so the result is there right away.
+    pub fn raycast(&self, ray: &Ray) -> Option<(usize, Hit)> {
+        self.instances.instances().iter()
+            .enumerate()
+            .filter_map(|(i, instance)| {
+                let to_model = instance.model_matrix().invert()?;
+                // distances along the ray don't change, only the point has to go back
+                let hit = self.obj_model.raycast(&ray.transform(&to_model))?;
+                Some((i, Hit { point: ray.at(hit.distance), ..hit }))
+            })
+            .min_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance))
+    }
+    // Shows the gizmo in mode, or hides it if it's already in that mode. The
+    // gizmo needs a selection, so showing it selects the current instance.
+    fn set_gizmo_mode(&mut self, mode: GizmoMode) {
+        self.gizmo.cancel();
+        self.gizmo.mode = (self.gizmo.mode != Some(mode)).then_some(mode);
+        if self.gizmo.mode.is_some() && self.selection.is_empty() && !self.instances.is_empty() {
+            self.selection.select(self.selected_instance);
+        }
+        println!("Gizmo: {:?}", self.gizmo.mode);
+    }
+    // The instance the gizmo is on, if it's showing
+    fn gizmo_target(&self) -> Option<&Instance> {
+        if self.gizmo.mode.is_none() || !self.selection.contains(self.selected_instance) {
+            return None;
+        }
+        self.instances.get(self.selected_instance)
+    }
+    // The camera the active pane is drawn with
+    fn active_view(&self) -> (&Camera, &Projection) {
+        match self.active_pane {
+            0 => (&self.render_camera, &self.projection),
+            pane => (&self.panes[pane - 1].camera, &self.panes[pane - 1].projection),
+        }
+    }
+    // Keeps the gizmo the same size on screen, in the active pane
+    fn gizmo_size(&self, instance: &Instance) -> f32 {
+        let (camera, projection) = self.active_view();
+        let distance = (cgmath::Point3::from_vec(instance.position) - camera.position).dot(camera.forward());
+        projection.half_height_at(distance.max(0.01)) * GIZMO_SCREEN_SIZE
+    }
+    // From the active pane's camera through the cursor
+    fn cursor_ray(&self) -> Option<Ray> {
+        let (_, rect) = self.pane_rects().into_iter().find(|(pane, _)| *pane == self.active_pane)?;
+        let (camera, projection) = self.active_view();
+        let cursor = ((self.pos.0 - rect.x as f64) as f32, (self.pos.1 - rect.y as f64) as f32);
+        Ray::through_pixel(camera, projection, cursor, (rect.width as f32, rect.height as f32))
+    }
+    fn gizmo_hit(&self) -> Option<Handle> {
+        let instance = self.gizmo_target()?;
+        self.gizmo.hit(&self.cursor_ray()?, instance, self.gizmo_size(instance))
+    }
+    fn begin_gizmo_drag(&mut self) -> bool {
+        let (Some(instance), Some(ray)) = (self.gizmo_target().cloned(), self.cursor_ray()) else {
+            return false;
+        };
+        let size = self.gizmo_size(&instance);
+        self.gizmo.begin_drag(&ray, self.selected_instance, &instance, size)
+    }
+    /// Adds an instance to the scene as an edit that can be undone, and
+    /// returns its index
+    pub fn add_instance(&mut self, instance: Instance) -> usize {
+        let index = self.instances.add(instance.clone());
+        self.history.record(Edit::AddInstance { index, instance });
+        index
+    }
+    /// Removes an instance as an edit that can be undone. The last instance
+    /// takes its index.
+    pub fn remove_instance(&mut self, index: usize) -> Instance {
+        let instance = self.instances.remove(index);
+        self.instance_removed(index);
+        self.history.record(Edit::RemoveInstance { index, instance: instance.clone() });
+        instance
+    }
+    // Keeps the selection and pick on the same instances after
+    // instances.remove(index) moved the last one into index
+    fn instance_removed(&mut self, index: usize) {
+        let last = self.instances.len();
+        self.gizmo.cancel();
+        self.selection.removed(index, last);
+        self.selected_instance = index_after_remove(self.selected_instance, index, last)
+            .or(self.selection.instances().last().copied())
+            .unwrap_or(0);
+        self.picked = self.picked.and_then(|pick| {
+            Some(Pick { instance: index_after_remove(pick.instance, index, last)?, ..pick })
+        });
+    }
+    // The same after instances.insert(index), which undoes a removal
+    fn instance_inserted(&mut self, index: usize) {
+        let last = self.instances.len() - 1;
+        self.gizmo.cancel();
+        self.selection.inserted(index, last);
+        self.selected_instance = index_after_insert(self.selected_instance, index, last);
+        if let Some(pick) = &mut self.picked {
+            pick.instance = index_after_insert(pick.instance, index, last);
+        }
+    }
+    pub fn light(&self) -> Light {
+        Light { position: self.light_position, color: self.light_uniform.color }
+    }
+    /// Moves or recolors the light as an edit that can be undone. Calls in
+    /// quick succession, e.g. from a slider, undo as one.
+    pub fn set_light(&mut self, light: Light) {
+        let before = self.light();
+        self.apply_light(light);
+        self.history.record_continuous(Edit::Light { before, after: light }, instant::Instant::now());
+    }
+    fn apply_light(&mut self, light: Light) {
+        self.light_position = light.position;
+        self.previous_light_position = light.position;
+        self.light_uniform.color = light.color;
+    }
+    /// Puts texture into slot of the model's material as an edit that can be
+    /// undone
+    pub fn set_material_texture(&mut self, material: usize, slot: TextureSlot, mut texture: Texture) {
+        self.obj_model.materials[material].swap_texture(&self.device, &self.texture_bind_group_layout, slot, &mut texture);
+        self.history.record(Edit::MaterialTexture { material, slot, texture });
+    }
+    /// Reverts the newest edit, returns false if there was nothing to undo
+    pub fn undo(&mut self) -> bool {
+        let Some(mut edit) = self.history.undo() else {
+            return false;
+        };
+        println!("Undo: {}", edit.label());
+        self.apply_edit(&mut edit, false);
+        self.history.undone(edit);
+        true
+    }
+    /// Reapplies the last undone edit, returns false if there was nothing to redo
+    pub fn redo(&mut self) -> bool {
+        let Some(mut edit) = self.history.redo() else {
+            return false;
+        };
+        println!("Redo: {}", edit.label());
+        self.apply_edit(&mut edit, true);
+        self.history.redone(edit);
+        true
+    }
+    // Applies edit, or reverts it when forward is false
+    fn apply_edit(&mut self, edit: &mut Edit, forward: bool) {
+        // undoing a removal adds the instance back and the other way around
+        let adding = matches!(edit, Edit::AddInstance { .. }) == forward;
+        match edit {
+            Edit::Transform { instance, before, after } => {
+                self.gizmo.cancel();
+                let transform = if forward { after } else { before };
+                self.instances.place(*instance, transform.clone());
+            }
+            Edit::AddInstance { index, instance } | Edit::RemoveInstance { index, instance } => {
+                if adding {
+                    self.instances.insert(*index, instance.clone());
+                    self.instance_inserted(*index);
+                } else {
+                    self.instances.remove(*index);
+                    self.instance_removed(*index);
+                }
+            }
+            Edit::Light { before, after } => self.apply_light(if forward { *after } else { *before }),
+            Edit::MaterialTexture { material, slot, texture } => {
+                self.obj_model.materials[*material].swap_texture(&self.device, &self.texture_bind_group_layout, *slot, texture);
+            }
+        }
+    }
+    /// Picks whatever is at (x, y) on the main target in the next frame. The
+    /// result shows up in `picked` a few frames later.
+    pub fn request_pick(&mut self, x: u32, y: u32) {
+        self.pick_requested = Some((x, y));
+    }
+    // Takes finished pick readbacks without blocking
+    fn update_pick(&mut self) {
+        if !self.picker.is_pending() {
+            return;
+        }
+        self.device.poll(wgpu::Maintain::Poll);
+        let Some(id) = self.picker.poll() else {
+            return;
+        };
+        self.picked = decode_pick_id(id, self.obj_model.meshes.len() as u32)
+            .filter(|&(instance, _)| instance < self.instances.len())
+            .map(|(instance, mesh)| Pick {
+                instance,
+                mesh,
+                material: self.obj_model.meshes[mesh].material,
+            });
+        match self.picked {
+            Some(pick) => {
+                // the rotation hotkeys act on what was clicked
+                self.selected_instance = pick.instance;
+                if self.pick_extends_selection {
+                    self.selection.toggle(pick.instance);
+                } else {
+                    self.selection.select(pick.instance);
+                }
+                println!("Picked {}", self.pick_summary(&pick));
+            }
+            None => {
+                if !self.pick_extends_selection {
+                    self.selection.clear();
+                }
+                println!("Picked nothing");
+            }
+        }
+    }
+    fn pick_summary(&self, pick: &Pick) -> String {
+        let material = self.obj_model.materials.get(pick.material).map_or("none", |m| m.name.as_str());
+        format!(
+            "instance {}, mesh {} ({}), material {} ({})",
+            pick.instance, pick.mesh, self.obj_model.meshes[pick.mesh].name, pick.material, material,
+        )
+    }
+    /// Splits the main target into 1, 2 or 4 panes. Extra panes look at the
+    /// scene from the front, right and top in orthographic, and keep their
+    /// cameras when the layout changes.
+    pub fn set_split_layout(&mut self, layout: SplitLayout) {
+        const PRESETS: [ViewPreset; 3] = [ViewPreset::Front, ViewPreset::Right, ViewPreset::Top];
+        while self.panes.len() < layout.panes() - 1 {
+            let preset = PRESETS[self.panes.len()];
+            let (target, distance) = match self.scene_aabb() {
+                Some(aabb) => (aabb.center(), (aabb.max - aabb.min).magnitude() / 2.0 / (self.projection.fovy() / 2.0).sin()),
+                None => (cgmath::Point3::new(0.0, 0.0, 0.0), 10.0),
+            };
+            let (yaw, pitch) = preset.yaw_pitch();
+            let mut camera = Camera::new(target, yaw, pitch);
+            camera.position = target - camera.forward() * distance;
+            let (width, height) = self.target.size();
+            let mut projection = Projection::new(width, height, self.projection.fovy(), 0.1, self.projection.zfar());
+            projection.set_mode(ProjectionMode::Orthographic, distance);
+            self.panes.push(Viewport::new(&self.device, &self.camera_bind_group_layout, camera, projection));
+        }
+        self.split_layout = layout;
+        self.active_pane = self.active_pane.min(layout.panes() - 1);
+        self.maximized = false;
+        self.fit_panes();
+        println!("Split layout: {:?}", layout);
+    }
+    // The camera bind group a pane is drawn with
+    fn pane_camera(&self, pane: usize) -> &wgpu::BindGroup {
+        match pane {
+            0 => &self.camera_bind_group,
+            pane => &self.panes[pane - 1].bind_group,
+        }
+    }
+    // Visible panes and where they are on the main target
+    fn pane_rects(&self) -> Vec<(usize, PaneRect)> {
+        let (width, height) = self.target.size();
+        if self.maximized {
+            return vec![(self.active_pane, PaneRect { x: 0, y: 0, width, height })];
+        }
+        self.split_layout.rects(width, height).into_iter().enumerate().collect()
+    }
+    // Gives each visible pane its aspect ratio. Panes share the main pass, so
+    // they also share its depth mode.
+    fn fit_panes(&mut self) {
+        for (pane, rect) in self.pane_rects() {
+            if rect.width == 0 || rect.height == 0 {
+                continue;
+            }
+            if pane == 0 {
+                self.projection.resize(rect.width, rect.height);
+            } else {
+                self.panes[pane - 1].projection.resize(rect.width, rect.height);
+            }
+        }
+        match_depth_mode(&self.projection, self.panes.iter_mut().map(|pane| &mut pane.projection));
+    }
+    /// Switches the main camera and the split panes, which share its depth
+    /// buffer, between standard and reverse-Z depth
+    pub fn set_depth_mode(&mut self, depth_mode: DepthMode) {
+        self.projection.set_depth_mode(depth_mode);
+        match_depth_mode(&self.projection, self.panes.iter_mut().map(|pane| &mut pane.projection));
+    }
+    /// Opens another view of the scene in window, starting from the main
+    /// camera. It shares the device, models and instances, but has its own
+    /// fly camera and surface.
+    pub fn open_window(&mut self, window: Window) -> anyhow::Result<()> {
+        let (Some(instance), Some(adapter)) = (&self.instance, &self.adapter) else {
+            anyhow::bail!("renderers created with with_target can't open windows");
+        };
+        let surface = unsafe { instance.create_surface(&window) }?;
+        if !adapter.is_surface_supported(&surface) {
+            anyhow::bail!("the adapter can't present to this window");
+        }
+        let vsync = self.target.present_mode().is_none_or(|mode| mode == wgpu::PresentMode::Fifo);
+        let target = WindowTarget::new(window, surface, adapter, &self.device, vsync, Some(self.target.format()));
+        // the pipelines are built for the main target's format
+        if target.format() != self.target.format() {
+            anyhow::bail!("the window needs {:?}, the renderer draws {:?}", target.format(), self.target.format());
+        }
+        let (width, height) = target.size();
+        let mut projection = Projection::new(width, height, self.projection.fovy(), 0.1, self.projection.zfar());
+        projection.set_depth_mode(self.projection.depth_mode());
+        let viewport = Viewport::new(&self.device, &self.camera_bind_group_layout, self.render_camera.clone(), projection);
+        let depth_texture = texture::Texture::create_depth_texture(&self.device, (width, height), "view_window_depth_texture");
+        self.windows.push(ViewWindow { target, depth_texture, viewport, modifiers: ModifiersState::empty() });
+        println!("Opened window {}", self.windows.len() + 1);
+        Ok(())
+    }
+    pub fn close_window(&mut self, id: WindowId) {
+        self.windows.retain(|window| window.id() != id);
+        if self.focused_window == Some(id) {
+            self.focused_window = None;
+        }
+    }
+    pub fn has_window(&self, id: WindowId) -> bool {
+        self.windows.iter().any(|window| window.id() == id)
+    }
+    /// Input for one of the windows from open_window, returns true if used
+    pub fn window_input(&mut self, id: WindowId, event: &WindowEvent) -> bool {
+        let (actions, pressed) = self.window_event_actions(id, event);
+        let Some(window) = self.windows.iter_mut().find(|window| window.id() == id) else {
+            return false;
+        };
+        match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                window.modifiers = *modifiers;
                 true
             }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
+            WindowEvent::Focused(focused) => {
+                if *focused {
+                    self.focused_window = Some(id);
+                } else if self.focused_window == Some(id) {
+                    self.focused_window = None;
+                }
                 true
             }
-            _ => false,
-        }) || self.rotation_controller.process_events(event)
+            WindowEvent::Resized(size) => {
+                window.resize(&self.device, size.width, size.height);
+                true
+            }
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                window.resize(&self.device, new_inner_size.width, new_inner_size.height);
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                window.viewport.process_scroll(delta);
+                true
+            }
+            _ => {
+                let mut used = false;
+                for action in actions {
+                    used |= window.viewport.process_action(action, pressed);
+                }
+                used
+            }
+        }
+    }
+    // The camera keeps its position and direction, so both modes pick up
+    // where the other one left off
+    pub fn set_camera_mode(&mut self, mode: CameraMode) {
+        if mode == CameraMode::Orbit && self.camera_mode != CameraMode::Orbit {
+            let focus = self.scene_aabb().map_or(self.orbit_controller.target, |aabb| aabb.center());
+            self.orbit_controller.attach(&self.camera, focus);
+        }
+        self.camera_mode = mode;
+        println!("Camera mode: {:?}", mode);
+    }
+    // Orbit around the whole scene and back off until it fits the view
+    pub fn frame_scene(&mut self) {
+        let Some(aabb) = self.scene_aabb() else {
+            return;
+        };
+        self.set_camera_mode(CameraMode::Orbit);
+        self.orbit_controller.frame(&mut self.camera, &aabb, self.projection.fovy());
+        let depth = self.orbit_controller.distance + (aabb.max - aabb.min).magnitude();
+        if self.projection.zfar() < depth {
+            self.projection.set_zfar(depth);
+        }
+    }
+    fn camera_path_action(&mut self, action: Action) -> bool {
+        let time = self.data_uniform.time;
+        match action {
+            Action::PathAddKey => {
+                // keep appending after a loaded path
+                let next = self.camera_path.keyframes().last().map_or(0.0, |k| k.time + 1.0);
+                let start = *self.path_recording_start.get_or_insert(time - next);
+                self.camera_path.add(CameraKeyframe::from_camera(time - start, &self.camera, &self.projection));
+                println!("Camera keyframe {} at {:.2}s", self.camera_path.keyframes().len(), time - start);
+            }
+            Action::PathClear => {
+                self.camera_path.clear();
+                self.path_recording_start = None;
+                self.path_playback_start = None;
+                println!("Camera path cleared");
+            }
+            Action::PathPlay => {
+                self.path_playback_start = match self.path_playback_start {
+                    None if !self.camera_path.is_empty() => {
+                        // the orbit controller would pull the camera back to its target
+                        self.set_camera_mode(CameraMode::Fly);
+                        Some(time)
+                    }
+                    _ => None,
+                };
+                println!("Camera path playback: {}", self.path_playback_start.is_some());
+            }
+            Action::PathLooping => {
+                self.camera_path.looping = !self.camera_path.looping;
+                println!("Camera path looping: {}", self.camera_path.looping);
+            }
+            Action::PathInterpolation => {
+                self.camera_path.interpolation = self.camera_path.interpolation.next();
+                println!("Camera path interpolation: {:?}", self.camera_path.interpolation);
+            }
+            Action::PathSave => match self.camera_path.save(CAMERA_PATH_FILE) {
+                Ok(()) => println!("Saved camera path to {}", CAMERA_PATH_FILE),
+                Err(e) => println!("Failed to save camera path: {:?}", e),
+            },
+            Action::PathLoad => match CameraPath::load(CAMERA_PATH_FILE) {
+                Ok(path) => {
+                    println!("Loaded camera path with {} keyframes", path.keyframes().len());
+                    self.camera_path = path;
+                    self.path_recording_start = None;
+                }
+                Err(e) => println!("Failed to load camera path: {:?}", e),
+            },
+            _ => return false,
+        }
+        true
+    }
+    // A camera path is played from the start and ends the capture when it's
+    // done, once around for looping paths
+    pub fn start_capture(&mut self, mut settings: CaptureSettings) {
+        if !self.target.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            println!("Failed to start capture: the render target can't be copied from");
+            return;
+        }
+        if let Some(last) = self.camera_path.keyframes().last() {
+            let length = if self.camera_path.looping { self.camera_path.duration } else { last.time };
+            settings.max_frames.get_or_insert((length * settings.fps).ceil() as u32 + 1);
+            self.set_camera_mode(CameraMode::Fly);
+            self.path_playback_start = Some(self.data_uniform.time);
+        }
+        let (width, height) = self.target.size();
+        match FrameCapture::new(&self.device, settings, width, height, self.target.format()) {
+            Ok(capture) => {
+                println!("Capture started");
+                self.capture = Some(capture);
+            }
+            Err(e) => println!("Failed to start capture: {:?}", e),
+        }
+    }
+    // Frames a turntable of the scene unless a camera path is given, then
+    // captures it at a fixed timestep
+    pub fn start_render(&mut self, job: &RenderJob) -> anyhow::Result<()> {
+        let frames = match &job.camera_path {
+            Some(path) => {
+                self.camera_path = CameraPath::load(path)?;
+                job.frames
+            }
+            None => {
+                let frames = job.frames.unwrap_or(120);
+                self.frame_scene();
+                self.camera_path = CameraPath::turntable(
+                    self.orbit_controller.target,
+                    self.orbit_controller.distance,
+                    cgmath::Deg(-15.0).into(),
+                    self.projection.fovy(),
+                    frames as f32 / job.fps,
+                );
+                // stops one frame before the closing keyframe, which repeats
+                // the first one, so the frames loop seamlessly
+                Some(frames)
+            }
+        };
+        self.start_capture(CaptureSettings {
+            directory: job.out.clone(),
+            fps: job.fps,
+            encoder: job.encoder.as_ref()
+                .map(|command| command.split_whitespace().map(String::from).collect()),
+            max_frames: frames,
+            ..Default::default()
+        });
+        if self.capture.is_none() {
+            anyhow::bail!("the capture didn't start");
+        }
+        Ok(())
+    }
+    pub fn stop_capture(&mut self) {
+        if let Some(capture) = self.capture.take() {
+            match capture.finish() {
+                Ok(frames) => println!("Captured {} frames", frames),
+                Err(e) => println!("Capture failed: {:?}", e),
+            }
+        }
+    }
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) -> bool {
+        if !self.target.set_present_mode(&self.device, present_mode) {
+            println!("Present mode {:?} isn't supported, keeping {:?}", present_mode, self.target.present_mode());
+            return false;
+        }
+        println!("Present mode: {:?}", present_mode);
+        true
+    }
+    // Cycles Fifo (vsync), Mailbox and Immediate, skipping unsupported ones
+    fn next_present_mode(&mut self) {
+        const MODES: [wgpu::PresentMode; 3] = [
+            wgpu::PresentMode::Fifo,
+            wgpu::PresentMode::Mailbox,
+            wgpu::PresentMode::Immediate,
+        ];
+        let current = MODES.iter().position(|&mode| Some(mode) == self.target.present_mode()).unwrap_or(0);
+        let next = (1..=MODES.len())
+            .map(|i| MODES[(current + i) % MODES.len()])
+            .find(|mode| self.target.present_modes().contains(mode));
+        if let Some(mode) = next {
+            self.set_present_mode(mode);
+        }
+    }
+    fn take_screenshot(&self, encoder: &mut wgpu::CommandEncoder) -> Option<Screenshot> {
+        let texture = match self.target.texture() {
+            Some(texture) if self.target.usage().contains(wgpu::TextureUsages::COPY_SRC) => texture,
+            _ => {
+                println!("Screenshot failed: the render target can't be copied from");
+                return None;
+            }
+        };
+        let millis = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |time| time.as_millis());
+        let path = std::path::PathBuf::from("screenshots").join(format!("screenshot_{}.png", millis));
+        Screenshot::new(&self.device, encoder, texture, path)
+            .map_err(|e| println!("Screenshot failed: {:?}", e))
+            .ok()
     }
+    // Hands mapped screenshots to their writer threads without blocking
+    fn update_screenshots(&mut self) {
+        if self.screenshots.is_empty() {
+            return;
+        }
+        self.device.poll(wgpu::Maintain::Poll);
+        self.screenshots = std::mem::take(&mut self.screenshots)
+            .into_iter()
+            .filter_map(Screenshot::update)
+            .collect();
+    }
+    // Capturing replaces wall-clock frame time with a fixed step
+    pub fn frame_timestep(&self) -> Option<instant::Duration> {
+        self.capture.as_ref().map(FrameCapture::timestep)
+    }
+    // How far in front of the camera the scene is, the depth that keeps its
+    // size when switching projections
+    fn focus_distance(&self) -> f32 {
+        match self.camera_mode {
+            CameraMode::Orbit => self.orbit_controller.distance,
+            CameraMode::Fly => self.scene_aabb()
+                .map_or(10.0, |aabb| (aabb.center() - self.camera.position).dot(self.camera.forward()).max(0.1)),
+        }
+    }
+    fn scene_aabb(&self) -> Option<Aabb> {
+        let model_aabb = self.obj_model.aabb();
+        self.instances.raw().iter()
+            .map(|instance| model_aabb.transform(&cgmath::Matrix4::from(instance.model)))
+            .reduce(|a, b| a.union(&b))
+    }
+    // Input driven camera movement and animation run in fixed steps, then the
+    // rendered state is interpolated between the last two steps. The camera has
+    // its own clock so it still moves while the simulation is paused.
     pub fn update(&mut self, dt: instant::Duration) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
-        self.camera_uniform.update_view_proj(&self.camera, &self.projection);
-        self.queue.write_buffer(
-            &self.camera_buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_uniform]),
-        ); 
-        self.rotation_controller.update_matrix(&mut self.rotation, dt.as_secs_f32());
-        self.transformation_uniform.update_rotation(&self.rotation);
-        self.queue.write_buffer(
-            &self.transformation_buffer,
-            0,
-            bytemuck::cast_slice(&[self.transformation_uniform]),
-        );
-        self.data_uniform.update(dt.as_secs_f32());
+        for _ in 0..self.camera_clock.advance(dt) {
+            self.step_camera(self.camera_clock.step());
+        }
+        for _ in 0..self.simulation.advance(dt) {
+            self.step_simulation(self.simulation.step());
+        }
+        self.update_frame(dt);
+    }
+    fn step_camera(&mut self, dt: instant::Duration) {
+        self.previous_camera = self.camera.clone();
+        match self.camera_mode {
+            CameraMode::Fly => self.camera_controller.update_camera(&mut self.camera, dt),
+            CameraMode::Orbit => {
+                self.orbit_controller.update_camera(&mut self.camera, dt);
+                // dolly zooms the ortho extent as well
+                self.projection.set_focus_distance(self.orbit_controller.distance);
+            }
+        }
+        self.projection.update(dt);
+        for viewport in self.panes.iter_mut().chain(self.windows.iter_mut().map(|window| &mut window.viewport)) {
+            viewport.step(dt);
+        }
+    }
+    fn step_simulation(&mut self, dt: instant::Duration) {
+        let dt = dt.as_secs_f32();
+        self.instances.begin_step();
+        if let Some(instance) = self.instances.get(self.selected_instance) {
+            let before = instance.clone();
+            let mut instance = instance.clone();
+            if self.rotation_controller.update_instance(&mut instance, dt) {
+                self.instances.set(self.selected_instance, instance.clone());
+                // holding the keys down undoes as one edit
+                self.history.record_continuous(
+                    Edit::Transform { instance: self.selected_instance, before, after: instance },
+                    instant::Instant::now(),
+                );
+            }
+        }
+
+        // orbit the light
+        self.previous_light_position = self.light_position;
+        self.light_position = cgmath::Quaternion::from_axis_angle(cgmath::Vector3::unit_y(), cgmath::Deg(60.0 * dt))
+            * self.light_position;
+
+        if self.stress_test {
+            let time = self.simulation.time.as_secs_f32();
+            let per_row = STRESS_TEST_INSTANCES_PER_ROW as usize;
+            self.instances.update_all(|i, instance| {
+                let (x, z) = ((i % per_row) as f32, (i / per_row) as f32);
+                instance.position.y = (time * 2.0 + x * 0.3 + z * 0.2).sin();
+            });
+        }
+    }
+    fn update_frame(&mut self, dt: instant::Duration) {
+        let alpha = self.simulation.alpha();
+        self.data_uniform.update(dt.as_secs_f32() * self.simulation.time_scale, self.simulation.interpolated_time());
         self.queue.write_buffer(
             &self.data_buffer,
             0,
             bytemuck::cast_slice(&[self.data_uniform]),
         );
 
-        // update light 
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
-        self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), 
-             cgmath::Deg(60.0 * dt.as_secs_f32()))
-                * old_position)
-                .into();
+        // camera paths follow simulation time, so pausing pauses playback too
+        let playback = self.path_playback_start
+            .and_then(|start| self.camera_path.sample(self.data_uniform.time - start));
+        if let Some(keyframe) = playback {
+            keyframe.apply(&mut self.camera, &mut self.projection);
+            self.previous_camera = self.camera.clone();
+        }
+        self.render_camera = self.previous_camera.lerp(&self.camera, self.camera_clock.alpha());
+        self.camera_uniform.update_view_proj(&self.render_camera, &self.projection);
+        let camera_alpha = self.camera_clock.alpha();
+        for viewport in self.panes.iter_mut().chain(self.windows.iter_mut().map(|window| &mut window.viewport)) {
+            viewport.upload(&self.queue, camera_alpha);
+        }
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        ); 
+
+        self.light_uniform.position = self.previous_light_position.lerp(self.light_position, alpha).into();
         self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light_uniform]));
 
+        self.instances.interpolate(alpha);
+        self.update_instances();
+        self.update_screenshots();
+        self.update_pick();
+        self.cull();
+        if self.stats.update(dt.as_secs_f32()) {
+            let cap = self.frame_limiter.fps().map_or("uncapped".to_string(), |fps| format!("cap {}", fps));
+            if let (Some(window), Some(present_mode)) = (self.target.window(), self.target.present_mode()) {
+                let picked = self.picked.map_or(String::new(), |pick| format!(" | picked {}", self.pick_summary(&pick)));
+                let history = if self.show_history { format!(" | {}", self.history.summary()) } else { String::new() };
+                window.set_title(&format!("{} | {:?}, {}{}{}", self.stats.summary(), present_mode, cap, picked, history));
+            }
+        }
+
+        self.debug.clear();
+        if self.show_gizmos {
+            self.draw_gizmos();
+        }
+        self.gizmo_draw.clear();
+        if let Some(instance) = self.gizmo_target().cloned() {
+            let size = self.gizmo_size(&instance);
+            self.gizmo.draw(&mut self.gizmo_draw, &instance, size);
+        }
+    }
+    /// Loads an .obj with this renderer's device and material layout
+    pub fn load_model(&self, model: &ModelPath) -> anyhow::Result<Model> {
+        pollster::block_on(load_model_obj(
+            &model.file_name,
+            &model.subfolder,
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+        ))
+    }
+    /// Shows scene from the next frame on and returns the one it replaces
+    pub fn set_scene(&mut self, scene: Scene) -> Scene {
+        let previous = Scene::new(
+            std::mem::replace(&mut self.obj_model, scene.model),
+            self.instances.instances().to_vec(),
+        );
+        self.instances.replace_all(scene.instances);
+        self.selected_instance = 0;
+        self.picked = None;
+        self.selection.clear();
+        self.gizmo.cancel();
+        // edits point at instances and materials of the old scene
+        self.history.clear();
+        // sphere buffer and mesh count belong to the model
+        self.gpu_culler = GpuCuller::new(&self.device, &self.obj_model, self.instances.buffer(), self.instances.len() as u32);
+        previous
     }
+    fn toggle_stress_test(&mut self) {
+        let scene = match self.stashed_scene.take() {
+            Some(scene) => scene,
+            None => {
+                let cube = self.load_model(&ModelPath {
+                    file_name: "cube.obj".to_string(),
+                    subfolder: "cube".to_string(),
+                }).unwrap();
+                Scene::new(cube, stress_test_instances(STRESS_TEST_INSTANCES_PER_ROW, 3.0))
+            }
+        };
+        self.stashed_scene = Some(self.set_scene(scene));
+        self.stress_test = !self.stress_test;
+        println!("Stress test: {} ({} instances)", self.stress_test, self.instances.len());
+    }
+    // Uploads instance edits and rebinds the GPU culler if the buffer changed
+    fn update_instances(&mut self) {
+        let num_instances = self.instances.len() as u32;
+        let recreated = self.instances.update(&self.device, &self.queue);
+        if recreated || num_instances != self.gpu_culler.num_instances {
+            self.gpu_culler.set_instance_buffer(&self.device, self.instances.buffer(), num_instances, recreated);
+        }
+    }
+    fn cull(&mut self) {
+        let instance_data = self.instances.raw();
+        // GPU culling results never come back to the CPU, so there's nothing to count
+        if self.cull_mode != CullMode::Cpu {
+            let total = self.instances.len() as u32;
+            let meshes = self.obj_model.meshes.len() as u32;
+            self.stats.cull = crate::culling::CullStats {
+                instances_drawn: total,
+                meshes_drawn: total * meshes,
+                ..Default::default()
+            };
+            return;
+        }
+
+        let frustum = Frustum::new(&self.render_camera, &self.projection);
+        let result = cull_model(&frustum, &self.obj_model, instance_data);
+        if result.instances.len() > self.culled_instance_capacity {
+            self.culled_instance_capacity = result.instances.len().next_power_of_two();
+            self.culled_instance_buffer = create_culled_instance_buffer(&self.device, self.culled_instance_capacity);
+        }
+        self.queue.write_buffer(&self.culled_instance_buffer, 0, bytemuck::cast_slice(&result.instances));
+        self.mesh_instances = result.mesh_instances;
+        self.stats.cull = result.stats;
+    }
+    fn draw_gizmos(&mut self) {
+        let model_aabb = self.obj_model.aabb();
+        let size = (model_aabb.max - model_aabb.min).magnitude() * 0.1;
+        self.debug.axes(cgmath::Point3::new(0.0, 0.0, 0.0), size);
+
+        let light_position = cgmath::Point3::from(self.light_uniform.position);
+        self.debug.sphere(light_position, size * 0.1, self.light_uniform.color);
+        self.debug.line(light_position, cgmath::Point3::new(light_position.x, 0.0, light_position.z), self.light_uniform.color);
+
+        for instance in self.instances.raw() {
+            let model_matrix = cgmath::Matrix4::from(instance.model);
+            let world_aabb = model_aabb.transform(&model_matrix);
+            self.debug.aabb(world_aabb.min, world_aabb.max, [1.0, 1.0, 0.0]);
+            for mesh in &self.obj_model.meshes {
+                let mesh_aabb = mesh.aabb.transform(&model_matrix);
+                self.debug.aabb(mesh_aabb.min, mesh_aabb.max, [0.0, 1.0, 1.0]);
+            }
+        }
+
+    }
+    /// Draws and presents the state from the last update. Fails with
+    /// SurfaceError::Lost for a ViewTarget that hasn't been given a view yet.
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.debug.upload(&self.device, &self.queue);
+        self.gizmo_draw.upload(&self.device, &self.queue);
+        self.fit_to_target();
+        self.target.begin_frame(&self.device)?;
+        let view = self.target.view();
         let mut encoder = self.device.create_command_encoder(
             &wgpu::CommandEncoderDescriptor { label: Some("Render Encoder")}
         );
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        // set color based on mouse position
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.5,
-                            g: 0.5,
-                            b: 0.5,
-                            a: 1.0, 
-                        }),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }), 
-                    stencil_ops: None,
-                }),
-            });
-            
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
-            /*use crate::model::DrawLight;
-            
-            render_pass.set_pipeline(&self.render_pipelines[1]); 
-            (render_pass.draw_light_model(
-                &self.obj_model,
-                &[
-                    &self.camera_bind_group,
-                    &self.light_bind_group,
-                ]
-            );*/
-
-            render_pass.set_pipeline(&self.render_pipelines[0]);
-            render_pass.draw_model_instanced(
+        let depth_mode = self.projection.depth_mode();
+        if self.cull_mode == CullMode::Gpu {
+            self.gpu_culler.cull(
+                &mut encoder,
+                &self.queue,
+                &Frustum::new(&self.render_camera, &self.projection),
                 &self.obj_model,
-                0..self.instances.len() as u32,
-                &[
-                    &self.camera_bind_group,
-                    &self.transformation_bind_group,
-                    &self.data_bind_group,
-                    &self.light_bind_group,
-                ]
             );
         }
+        {
+            let mut render_pass = begin_scene_pass(&mut encoder, view, &self.depth_texture.view, depth_mode);
+            for (pane, rect) in self.pane_rects() {
+                if rect.width == 0 || rect.height == 0 {
+                    continue;
+                }
+                render_pass.set_viewport(rect.x as f32, rect.y as f32, rect.width as f32, rect.height as f32, 0.0, 1.0);
+                render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+                // culling only runs for the main camera
+                let cull_mode = if pane == 0 { self.cull_mode } else { CullMode::Off };
+                self.draw_scene(&mut render_pass, self.pane_camera(pane), depth_mode, cull_mode);
+                // sized and hit-tested for the active pane's camera only
+                if pane == self.active_pane {
+                    self.gizmo_draw.draw(&mut render_pass, depth_mode, self.pane_camera(pane));
+                }
+            }
+        }
+        self.selection_renderer.prepare(&self.device, &self.queue, &self.selection, self.target.size());
+        let panes = self.pane_rects().into_iter()
+            .filter(|(_, rect)| rect.width > 0 && rect.height > 0)
+            .map(|(pane, rect)| (rect, self.pane_camera(pane)))
+            .collect::<Vec<_>>();
+        self.selection_renderer.draw(
+            &mut encoder,
+            view,
+            panes.iter().copied(),
+            &self.selection,
+            &self.obj_model,
+            self.instances.buffer(),
+        );
+        if let Some((x, y)) = self.pick_requested.take() {
+            let hit = self.pane_rects().into_iter().find(|(_, rect)| rect.contains(x as f64, y as f64));
+            if let Some((pane, rect)) = hit {
+                // picker is borrowed mutably, so no pane_camera
+                let camera_bind_group = match pane {
+                    0 => &self.camera_bind_group,
+                    pane => &self.panes[pane - 1].bind_group,
+                };
+                self.picker.encode(
+                    &self.device,
+                    &self.queue,
+                    &mut encoder,
+                    (x, y),
+                    rect,
+                    self.target.size(),
+                    camera_bind_group,
+                    depth_mode,
+                    &self.obj_model,
+                    self.instances.buffer(),
+                    self.instances.len() as u32,
+                );
+            }
+        }
 
+        let copied = match (&self.capture, self.target.texture()) {
+            (Some(capture), Some(texture)) => capture.copy_frame(&mut encoder, texture),
+            (Some(_), None) => Err(anyhow::anyhow!("the render target has no texture to copy")),
+            (None, _) => Ok(()),
+        };
+        let screenshot = if std::mem::take(&mut self.screenshot_requested) {
+            self.take_screenshot(&mut encoder)
+        } else {
+            None
+        };
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        self.picker.submit();
+        if let Some(mut screenshot) = screenshot {
+            screenshot.submit_copy();
+            self.screenshots.push(screenshot);
+        }
+        if let Some(capture) = &mut self.capture {
+            let written = copied.and_then(|_| capture.write_frame(&self.device));
+            if let Err(e) = written {
+                println!("Capture failed: {:?}", e);
+                self.stop_capture();
+            } else if capture.is_done() {
+                self.stop_capture();
+            }
+        }
+        self.target.end_frame();
+        self.render_windows();
 
         Ok(())
     }
+    // The scene as seen through camera_bind_group, into a pass from begin_scene_pass
+    fn draw_scene<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        depth_mode: DepthMode,
+        cull_mode: CullMode,
+    ) {
+        /*use crate::model::DrawLight;
+        
+        render_pass.set_pipeline(&self.render_pipelines[1][depth_mode as usize]); 
+        (render_pass.draw_light_model(
+            &self.obj_model,
+            &[
+                &self.camera_bind_group,
+                &self.light_bind_group,
+            ]
+        );*/
+
+        render_pass.set_pipeline(&self.render_pipelines[0][depth_mode as usize]);
+        let bind_groups = [
+            camera_bind_group,
+            &self.data_bind_group,
+            &self.light_bind_group,
+        ];
+        match cull_mode {
+            CullMode::Off => {
+                render_pass.set_vertex_buffer(1, self.instances.buffer().slice(..));
+                render_pass.draw_model_instanced(
+                    &self.obj_model,
+                    0..self.instances.len() as u32,
+                    &bind_groups,
+                );
+            }
+            CullMode::Cpu => {
+                render_pass.set_vertex_buffer(1, self.culled_instance_buffer.slice(..));
+                render_pass.draw_model_culled(&self.obj_model, &self.mesh_instances, &bind_groups);
+            }
+            CullMode::Gpu => {
+                render_pass.draw_model_indirect(
+                    &self.obj_model,
+                    &self.gpu_culler.indirect_buffer,
+                    &self.gpu_culler.culled_instance_buffer,
+                    self.gpu_culler.num_instances,
+                    &bind_groups,
+                );
+            }
+        }
+
+        self.debug_view_renderer.draw(
+            render_pass,
+            self.debug_view,
+            depth_mode,
+            &self.obj_model,
+            self.instances.buffer(),
+            0..self.instances.len() as u32,
+            &[camera_bind_group],
+        );
+        self.debug.draw(render_pass, depth_mode, camera_bind_group);
+    }
+    // Extra windows draw every instance, culling and selection outlines only
+    // run for the main target
+    fn render_windows(&mut self) {
+        // taken out so draw_scene can borrow the rest of self
+        let mut windows = std::mem::take(&mut self.windows);
+        for window in &mut windows {
+            if let Err(e) = window.target.begin_frame(&self.device) {
+                if matches!(e, wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) {
+                    let (width, height) = window.target.size();
+                    window.resize(&self.device, width, height);
+                } else {
+                    println!("Window {:?}: {:?}", window.id(), e);
+                }
+                continue;
+            }
+            let mut encoder = self.device.create_command_encoder(
+                &wgpu::CommandEncoderDescriptor { label: Some("Window Render Encoder") }
+            );
+            let depth_mode = window.viewport.projection.depth_mode();
+            {
+                let mut render_pass = begin_scene_pass(&mut encoder, window.target.view(), &window.depth_texture.view, depth_mode);
+                self.draw_scene(&mut render_pass, &window.viewport.bind_group, depth_mode, CullMode::Off);
+            }
+            self.queue.submit(std::iter::once(encoder.finish()));
+            window.target.end_frame();
+        }
+        self.windows = windows;
+    }
 }
 
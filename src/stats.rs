@@ -0,0 +1,104 @@
+use crate::culling::CullStats;
+
+// In seconds
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameTimeStats {
+    pub average: f32,
+    pub min: f32,
+    pub max: f32,
+    pub p99: f32,
+}
+
+impl FrameTimeStats {
+    // Sorts frame_times in place
+    pub fn from_frame_times(frame_times: &mut [f32]) -> Self {
+        if frame_times.is_empty() {
+            return Self::default();
+        }
+        frame_times.sort_by(f32::total_cmp);
+        let last = frame_times.len() - 1;
+        Self {
+            average: frame_times.iter().sum::<f32>() / frame_times.len() as f32,
+            min: frame_times[0],
+            max: frame_times[last],
+            p99: frame_times[(last as f32 * 0.99).round() as usize],
+        }
+    }
+}
+
+// Shown in the window title, refreshed once a second
+pub struct Stats {
+    frames: u32,
+    elapsed: f32,
+    // this second's frame times in seconds
+    frame_times: Vec<f32>,
+    pub fps: f32,
+    pub frame_time: FrameTimeStats,
+    pub cull: CullStats,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            frames: 0,
+            elapsed: 0.0,
+            frame_times: Vec::new(),
+            fps: 0.0,
+            frame_time: FrameTimeStats::default(),
+            cull: CullStats::default(),
+        }
+    }
+
+    // Returns true when a new summary is ready
+    pub fn update(&mut self, dt: f32) -> bool {
+        self.frames += 1;
+        self.elapsed += dt;
+        self.frame_times.push(dt);
+        if self.elapsed < 1.0 {
+            return false;
+        }
+        self.fps = self.frames as f32 / self.elapsed;
+        self.frame_time = FrameTimeStats::from_frame_times(&mut self.frame_times);
+        self.frame_times.clear();
+        self.frames = 0;
+        self.elapsed = 0.0;
+        true
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{:.0} fps | frame {:.2} ms avg, {:.2} min, {:.2} max, {:.2} p99 | instances {} drawn / {} culled | meshes {} drawn / {} culled",
+            self.fps,
+            self.frame_time.average * 1000.0,
+            self.frame_time.min * 1000.0,
+            self.frame_time.max * 1000.0,
+            self.frame_time.p99 * 1000.0,
+            self.cull.instances_drawn,
+            self.cull.instances_culled,
+            self.cull.meshes_drawn,
+            self.cull.meshes_culled,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_time_stats() {
+        let mut frame_times = (1..=100).map(|i| i as f32 / 1000.0).rev().collect::<Vec<_>>();
+        let stats = FrameTimeStats::from_frame_times(&mut frame_times);
+        assert_eq!(stats.min, 0.001);
+        assert_eq!(stats.max, 0.1);
+        assert_eq!(stats.p99, 0.099);
+        assert!((stats.average - 0.0505).abs() < 1e-6);
+        assert_eq!(FrameTimeStats::from_frame_times(&mut []), FrameTimeStats::default());
+    }
+}
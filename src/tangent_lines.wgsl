@@ -0,0 +1,85 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// The mesh vertex buffer is bound with an instance step mode, so every model
+// vertex becomes one instance made up of three lines (six vertices)
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec3<f32>,
+    @location(4) bitangent: vec3<f32>,
+};
+
+// The instance buffer is bound with a stride of zero, so this is the same
+// transform for the whole draw
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+};
+
+// fraction of the distance to the camera, keeps lines readable regardless of model scale
+const LINE_LENGTH: f32 = 0.02;
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let normal_matrix = mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    let line = vertex_index / 2u;
+    var direction: vec3<f32>;
+    var out: VertexOutput;
+    if line == 0u {
+        direction = model.tangent;
+        out.color = vec3<f32>(1.0, 0.0, 0.0);
+    } else if line == 1u {
+        direction = model.bitangent;
+        out.color = vec3<f32>(0.0, 1.0, 0.0);
+    } else {
+        direction = model.normal;
+        out.color = vec3<f32>(0.0, 0.0, 1.0);
+    }
+
+    var position = world_position.xyz;
+    if vertex_index % 2u == 1u {
+        let line_length = LINE_LENGTH * distance(position, camera.view_pos.xyz);
+        // normals need the inverse-transpose, tangents the plain model matrix
+        let model_matrix_3 = mat3x3<f32>(model_matrix[0].xyz, model_matrix[1].xyz, model_matrix[2].xyz);
+        let transformed = select(model_matrix_3 * direction, normal_matrix * direction, line == 2u);
+        position += normalize(transformed) * line_length;
+    }
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color, 1.0);
+}
@@ -10,10 +10,10 @@ pub struct Texture {
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+    pub fn create_depth_texture(device: &wgpu::Device, (width, height): (u32, u32), label: &str) -> Self {
         let size = wgpu::Extent3d {
-            width: config.width,
-            height: config.height,
+            width: width.max(1),
+            height: height.max(1),
             depth_or_array_layers: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -56,7 +56,7 @@ impl Texture {
     ) -> Result<Self> {
         // have to flip vertically because wgpu's image loading is dogwater
         let img = &image::load_from_memory(bytes)?.flipv();
-        Self::from_image(device, queue, &img, Some(label), is_normal_map)
+        Self::from_image(device, queue, img, Some(label), is_normal_map)
     }
 
     pub fn from_image(
@@ -154,7 +154,7 @@ pub fn generate_placeholder_texture(
         for x in 0..width {
             for y in 0..height {
                 if ((x + y) % 2) == 1 {
-                    rgba.put_pixel(x.into(), y.into(), color);
+                    rgba.put_pixel(x, y, color);
                 }
             }
         }
@@ -162,7 +162,7 @@ pub fn generate_placeholder_texture(
         // def a better way to do this but idrc i'll fix it later
         for x in 0..width {
             for y in 0..height {
-                rgba.put_pixel(x.into(), y.into(), color);
+                rgba.put_pixel(x, y, color);
             }
         }
     }
@@ -0,0 +1,122 @@
+use instant::Duration;
+
+pub const SIMULATION_HZ: u32 = 120;
+
+// Past this many steps in one frame the rest of the backlog is dropped, so a
+// long stall doesn't turn into a spiral of ever longer catch-up frames
+const MAX_STEPS_PER_FRAME: u32 = 8;
+
+// Turns variable frame times into a whole number of fixed steps. Whatever is
+// left over is the fraction of a step to interpolate the rendered state by.
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+    pub time_scale: f32,
+    pub paused: bool,
+    // steps to run while paused, see single_step
+    queued_steps: u32,
+    // total simulated time
+    pub time: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(hz: u32) -> Self {
+        Self {
+            step: Duration::from_secs(1) / hz,
+            accumulator: Duration::ZERO,
+            time_scale: 1.0,
+            paused: false,
+            queued_steps: 0,
+            time: Duration::ZERO,
+        }
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.accumulator = Duration::ZERO;
+    }
+
+    // Advances exactly one step on the next frame, only while paused
+    pub fn single_step(&mut self) {
+        if self.paused {
+            self.queued_steps += 1;
+        }
+    }
+
+    // Returns how many steps to simulate this frame
+    pub fn advance(&mut self, frame_time: Duration) -> u32 {
+        let steps = if self.paused {
+            std::mem::take(&mut self.queued_steps)
+        } else {
+            // f64 so a time scale of 1 keeps every nanosecond
+            self.accumulator += frame_time.mul_f64(self.time_scale as f64);
+            let steps = (self.accumulator.as_nanos() / self.step.as_nanos()) as u32;
+            self.accumulator -= self.step * steps;
+            if steps > MAX_STEPS_PER_FRAME {
+                self.accumulator = Duration::ZERO;
+            }
+            steps.min(MAX_STEPS_PER_FRAME)
+        };
+        self.time += self.step * steps;
+        steps
+    }
+
+    // How far the render is between the last two simulated states, 0..1
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+
+    // Simulated time as seen by the render, one step behind plus alpha
+    pub fn interpolated_time(&self) -> f32 {
+        (self.time.as_secs_f32() - self.step.as_secs_f32() * (1.0 - self.alpha())).max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulates_partial_steps() {
+        let mut timestep = FixedTimestep::new(100);
+        assert_eq!(timestep.advance(Duration::from_millis(25)), 2);
+        assert!((timestep.alpha() - 0.5).abs() < 1e-5);
+        assert_eq!(timestep.advance(Duration::from_millis(5)), 1);
+        assert!(timestep.alpha() < 1e-5);
+        assert_eq!(timestep.time, Duration::from_millis(30));
+    }
+
+    #[test]
+    fn same_steps_regardless_of_frame_rate() {
+        let mut slow = FixedTimestep::new(SIMULATION_HZ);
+        let mut fast = FixedTimestep::new(SIMULATION_HZ);
+        let slow_steps: u32 = (0..30).map(|_| slow.advance(Duration::from_secs(1) / 30)).sum();
+        let fast_steps: u32 = (0..144).map(|_| fast.advance(Duration::from_secs(1) / 144)).sum();
+        assert_eq!(slow_steps, SIMULATION_HZ);
+        assert!(fast_steps.abs_diff(SIMULATION_HZ) <= 1);
+    }
+
+    #[test]
+    fn pause_and_single_step() {
+        let mut timestep = FixedTimestep::new(100);
+        timestep.toggle_pause();
+        assert_eq!(timestep.advance(Duration::from_secs(1)), 0);
+        timestep.single_step();
+        assert_eq!(timestep.advance(Duration::from_secs(1)), 1);
+        assert_eq!(timestep.advance(Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn time_scale_and_stall() {
+        let mut timestep = FixedTimestep::new(100);
+        timestep.time_scale = 0.5;
+        assert_eq!(timestep.advance(Duration::from_millis(40)), 2);
+        timestep.time_scale = 1.0;
+        assert_eq!(timestep.advance(Duration::from_secs(10)), MAX_STEPS_PER_FRAME);
+        assert!(timestep.alpha() < 1e-5);
+    }
+}
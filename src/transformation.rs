@@ -1,52 +1,8 @@
-use cgmath::SquareMatrix;
-use winit::event::{KeyboardInput, VirtualKeyCode, ElementState, WindowEvent};
+use cgmath::Rotation3;
 
-pub struct RotationDeg {
-    pub yaw: f32,
-    pub pitch: f32,
-    pub roll: f32,
-}
-
-impl RotationDeg {
-    pub fn new() -> Self {
-        Self {
-            yaw: 0.0,
-            pitch: 0.0,
-            roll: 0.0,
-        }
-    }
+use crate::input_map::Action;
+use crate::instancing::Instance;
 
-    pub fn build_rotation_matrix(&self) -> cgmath::Matrix4<f32> {
-        let yaw = cgmath::Matrix4::from_angle_y(cgmath::Deg(self.yaw));
-        let pitch = cgmath::Matrix4::from_angle_x(cgmath::Deg(self.pitch));
-        let roll = cgmath::Matrix4::from_angle_z(cgmath::Deg(self.roll));
-        return yaw * pitch * roll;
-    }
-}
-
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct TransformationUniform{
-    rotation: [[f32; 4]; 4],
-    scaling: [[f32; 4]; 4],
-}
-
-impl TransformationUniform {
-    pub fn new() -> Self {
-        Self {
-            rotation: cgmath::Matrix4::identity().into(),
-            scaling: cgmath::Matrix4::identity().into(),
-        }
-    }
-
-    pub fn update_rotation(&mut self, rotation: &RotationDeg) {
-        self.rotation = rotation.build_rotation_matrix().into();
-    }
-
-    pub fn update_scaling(&mut self, scaling_factor: f32){
-        self.scaling = cgmath::Matrix4::from_scale(scaling_factor).into();
-    }
-}
 pub struct RotationController {
     speed: f32,
     is_forward_pressed: bool,
@@ -65,52 +21,39 @@ impl RotationController {
             is_right_pressed: false,
         }
     }
-    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
-        match event {
-            WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    state,
-                    virtual_keycode: Some(keycode),
-                    ..
-                },
-                ..
-            } => {
-                let is_pressed = *state == ElementState::Pressed;
-                match keycode {
-                    VirtualKeyCode::Up => {
-                        self.is_forward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::Left => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::Down => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    VirtualKeyCode::Right => {
-                        self.is_right_pressed = is_pressed;
-                        true
-                    }
-                    _ => false,
-                }
-            }
-            _ => false,
-        }
+    pub fn process_action(&mut self, action: Action, pressed: bool) -> bool {
+        match action {
+            Action::ModelRotateUp => self.is_forward_pressed = pressed,
+            Action::ModelRotateDown => self.is_backward_pressed = pressed,
+            Action::ModelRotateLeft => self.is_left_pressed = pressed,
+            Action::ModelRotateRight => self.is_right_pressed = pressed,
+            _ => return false,
+        }
+        true
     }
-   pub fn update_matrix(&mut self, rotation: &mut RotationDeg, delta_time: f32) {
+    // Rotates around the world axes, returns false if no key is held so the
+    // caller can skip re-uploading the instance
+    pub fn update_instance(&mut self, instance: &mut Instance, delta_time: f32) -> bool {
+        let mut pitch = 0.0;
+        let mut yaw = 0.0;
         if self.is_forward_pressed {
-            rotation.pitch += self.speed * delta_time;
+            pitch += self.speed * delta_time;
         }
         if self.is_backward_pressed {
-            rotation.pitch -= self.speed * delta_time;
+            pitch -= self.speed * delta_time;
         }
         if self.is_left_pressed {
-            rotation.yaw += self.speed * delta_time;
+            yaw += self.speed * delta_time;
         }
         if self.is_right_pressed {
-            rotation.yaw -= self.speed * delta_time;
+            yaw -= self.speed * delta_time;
         }
-    } 
+        if pitch == 0.0 && yaw == 0.0 {
+            return false;
+        }
+        instance.rotation = cgmath::Quaternion::from_angle_y(cgmath::Deg(yaw))
+            * cgmath::Quaternion::from_angle_x(cgmath::Deg(pitch))
+            * instance.rotation;
+        true
+    }
 }
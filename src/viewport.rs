@@ -0,0 +1,223 @@
+use wgpu::util::DeviceExt;
+use winit::event::{ModifiersState, MouseScrollDelta};
+use winit::window::WindowId;
+
+use crate::camera::{Camera, CameraController, CameraUniform, Projection, ProjectionMode};
+use crate::input_map::Action;
+use crate::render_target::{RenderTarget, WindowTarget};
+use crate::texture::Texture;
+
+// A free fly camera onto the shared scene. It has its own camera uniform, so
+// any number of them can be drawn in one frame next to the main camera.
+pub struct Viewport {
+    pub camera: Camera,
+    previous_camera: Camera,
+    pub projection: Projection,
+    pub controller: CameraController,
+    mouse_pressed: bool,
+    uniform: CameraUniform,
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Viewport {
+    pub fn new(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, camera: Camera, projection: Projection) -> Self {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera, &projection);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Viewport Camera Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("Viewport Camera Bind Group"),
+        });
+        Self {
+            previous_camera: camera.clone(),
+            camera,
+            projection,
+            controller: CameraController::new(4.0, 1.0),
+            mouse_pressed: false,
+            uniform,
+            buffer,
+            bind_group,
+        }
+    }
+
+    // Flies with the camera actions, looks around while camera.look is held
+    pub fn process_action(&mut self, action: Action, pressed: bool) -> bool {
+        if action == Action::CameraLook {
+            self.mouse_pressed = pressed;
+            return true;
+        }
+        self.controller.process_action(action, pressed)
+    }
+
+    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        match self.projection.mode() {
+            ProjectionMode::Perspective => self.controller.process_scroll(delta),
+            ProjectionMode::Orthographic => self.projection.zoom(delta),
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.mouse_pressed
+    }
+
+    pub fn process_mouse_motion(&mut self, dx: f64, dy: f64) {
+        if self.mouse_pressed {
+            self.controller.process_mouse(dx, dy);
+        }
+    }
+
+    // Runs on the renderer's camera clock
+    pub fn step(&mut self, dt: instant::Duration) {
+        self.previous_camera = self.camera.clone();
+        self.controller.update_camera(&mut self.camera, dt);
+        self.projection.update(dt);
+    }
+
+    // alpha is how far the camera clock is into the next step
+    pub fn upload(&mut self, queue: &wgpu::Queue, alpha: f32) {
+        let camera = self.previous_camera.lerp(&self.camera, alpha);
+        self.uniform.update_view_proj(&camera, &self.projection);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PaneRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PaneRect {
+    pub fn contains(&self, x: f64, y: f64) -> bool {
+        x >= self.x as f64
+            && y >= self.y as f64
+            && x < (self.x + self.width) as f64
+            && y < (self.y + self.height) as f64
+    }
+}
+
+// How the main target is split into panes. Pane 0 is the main camera, the
+// others are Viewports.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SplitLayout {
+    Single,
+    // side by side
+    Double,
+    // two by two
+    Quad,
+}
+
+impl SplitLayout {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Single => Self::Double,
+            Self::Double => Self::Quad,
+            Self::Quad => Self::Single,
+        }
+    }
+
+    pub fn panes(self) -> usize {
+        match self {
+            Self::Single => 1,
+            Self::Double => 2,
+            Self::Quad => 4,
+        }
+    }
+
+    // Row by row from the top left, covering width x height without gaps
+    pub fn rects(self, width: u32, height: u32) -> Vec<PaneRect> {
+        let (columns, rows) = match self {
+            Self::Single => (1, 1),
+            Self::Double => (2, 1),
+            Self::Quad => (2, 2),
+        };
+        let edge = |i: u32, count: u32, size: u32| size * i / count;
+        (0..rows).flat_map(|row| {
+            (0..columns).map(move |column| {
+                let (x, y) = (edge(column, columns, width), edge(row, rows, height));
+                PaneRect {
+                    x,
+                    y,
+                    width: edge(column + 1, columns, width) - x,
+                    height: edge(row + 1, rows, height) - y,
+                }
+            })
+        }).collect()
+    }
+}
+
+// Panes are drawn in the main target's scene pass against its depth buffer,
+// so their projections have to use the main depth mode
+pub fn match_depth_mode<'a>(main: &Projection, panes: impl IntoIterator<Item = &'a mut Projection>) {
+    for projection in panes {
+        projection.set_depth_mode(main.depth_mode());
+    }
+}
+
+// An extra window with its own camera, drawn by the renderer after the main
+// target each frame
+pub struct ViewWindow {
+    pub target: WindowTarget,
+    pub depth_texture: Texture,
+    pub viewport: Viewport,
+    // each window only hears about modifier changes while it has focus
+    pub modifiers: ModifiersState,
+}
+
+impl ViewWindow {
+    pub fn id(&self) -> WindowId {
+        self.target.window().expect("window targets have a window").id()
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.target.resize(device, width, height);
+        self.viewport.projection.resize(width, height);
+        self.depth_texture = Texture::create_depth_texture(device, (width, height), "view_window_depth_texture");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::DepthMode;
+
+    #[test]
+    fn panes_cover_the_target() {
+        for layout in [SplitLayout::Single, SplitLayout::Double, SplitLayout::Quad] {
+            let rects = layout.rects(801, 601);
+            assert_eq!(rects.len(), layout.panes());
+            let area: u32 = rects.iter().map(|r| r.width * r.height).sum();
+            assert_eq!(area, 801 * 601);
+        }
+        let quad = SplitLayout::Quad.rects(800, 600);
+        assert_eq!(quad[3], PaneRect { x: 400, y: 300, width: 400, height: 300 });
+        assert!(quad[1].contains(400.0, 0.0));
+        assert!(!quad[0].contains(400.0, 0.0));
+    }
+
+    #[test]
+    fn panes_follow_the_main_depth_mode() {
+        let projection = || Projection::new(800, 600, cgmath::Deg(45.0), 0.1, 100.0);
+        let mut main = projection();
+        let mut panes = [projection(), projection(), projection()];
+        for depth_mode in [DepthMode::ReverseZ, DepthMode::Standard] {
+            main.set_depth_mode(depth_mode);
+            match_depth_mode(&main, &mut panes);
+            assert!(panes.iter().all(|pane| pane.depth_mode() == depth_mode));
+        }
+    }
+}
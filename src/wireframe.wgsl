@@ -0,0 +1,62 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+};
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) barycentric: vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    // only meaningful for the de-indexed fallback buffer, where every three
+    // consecutive vertices make up one triangle
+    let corner = vertex_index % 3u;
+    out.barycentric = vec3<f32>(f32(corner == 0u), f32(corner == 1u), f32(corner == 2u));
+    return out;
+}
+
+// Used with PolygonMode::Line, the rasterizer already only produces edges
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}
+
+// Fallback for devices without POLYGON_MODE_LINE
+@fragment
+fn fs_barycentric(in: VertexOutput) -> @location(0) vec4<f32> {
+    let width = fwidth(in.barycentric);
+    let edge = smoothstep(vec3<f32>(0.0), width * 1.5, in.barycentric);
+    if min(min(edge.x, edge.y), edge.z) > 0.5 {
+        discard;
+    }
+    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+}